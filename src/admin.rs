@@ -0,0 +1,20 @@
+//! 与管理员相关的工具
+
+use miraie::prelude::*;
+
+use crate::Config;
+
+/// 私聊通知所有管理员，发送失败只记录日志
+pub async fn notify_admins(bot: &Bot, config: &Config, message: impl Into<MessageChain>) {
+    let message = message.into();
+    for admin in config.admins.iter() {
+        let request = api::send_friend_message::Request {
+            target: *admin,
+            message: message.clone(),
+            quote: None,
+        };
+        if let Err(e) = bot.request(request).await {
+            warn!("通知管理员 {} 失败：{:?}", admin.0, e);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! 审计日志
+//!
+//! reload、新日程表、生成日报、修改周报分类等特权操作记录在 sled 的 `audit` 树中，
+//! 键为记录时间与递增 id，值为 json。管理员可以用 `审计` 命令查看或导出，也可以用 `avabot export-audit` 导出全部记录。
+
+use chrono::{DateTime, Local, Utc};
+use std::fmt::Display;
+
+use crate::prelude::*;
+use crate::Store;
+
+const TREE: &str = "audit";
+
+/// 一次特权操作
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    /// 操作者
+    pub operator: QQ,
+    /// 操作所在的群，私聊为 `None`
+    pub group: Option<QQ>,
+    pub plugin: String,
+    pub action: String,
+    /// 触发操作的消息
+    pub command: String,
+    pub success: bool,
+    /// 操作结果或者错误
+    pub result: String,
+}
+
+impl Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let place = match self.group {
+            Some(group) => format!("群 {}", group.0),
+            None => "私聊".to_string(),
+        };
+        write!(
+            f,
+            "{} {} {} {}：{}（{}）",
+            self.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+            place,
+            self.operator.0,
+            self.command,
+            if self.success { "成功" } else { "失败" },
+            self.result
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct AuditLog {
+    db: sled::Db,
+    tree: sled::Tree,
+}
+
+impl AuditLog {
+    pub fn new(store: &Store) -> Result<Self> {
+        Ok(Self {
+            db: store.db().clone(),
+            tree: store.tree(TREE)?,
+        })
+    }
+
+    /// 写入一条记录
+    pub fn insert(&self, entry: &Entry) -> Result<()> {
+        // 键以时间开头，导入到另一个数据库后 id 从头开始也不会覆盖旧记录
+        let mut key = entry.time.timestamp_nanos().to_be_bytes().to_vec();
+        key.extend(self.db.generate_id()?.to_be_bytes());
+        self.tree.insert(key, serde_json::to_vec(entry)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// 记录 `msg` 触发的操作及其结果，写入失败时只记录日志
+    pub fn record<T, R, E>(
+        &self,
+        msg: &T,
+        plugin: &str,
+        action: &str,
+        result: &std::result::Result<R, E>,
+    ) where
+        T: Conversation + ConversationExt,
+        R: Display,
+        E: Display,
+    {
+        let (success, result) = match result {
+            Ok(r) => (true, r.to_string()),
+            Err(e) => (false, format!("{:#}", e)),
+        };
+        let entry = Entry {
+            time: Utc::now(),
+            operator: *msg.sender().as_ref(),
+            group: msg.group_id(),
+            plugin: plugin.to_string(),
+            action: action.to_string(),
+            command: msg.as_message().to_string(),
+            success,
+            result,
+        };
+        info!("审计：{}", entry);
+        if let Err(e) = self.insert(&entry) {
+            error!("写入审计日志失败：{:?}，{:?}", e, entry);
+        }
+    }
+
+    /// 最近的 `n` 条记录，新的在前
+    pub fn recent(&self, n: usize) -> Result<Vec<Entry>> {
+        self.tree
+            .iter()
+            .values()
+            .rev()
+            .take(n)
+            .map(|v| Ok(serde_json::from_slice(&v?)?))
+            .collect()
+    }
+
+    /// 全部记录，旧的在前
+    pub fn all(&self) -> Result<Vec<Entry>> {
+        self.tree
+            .iter()
+            .values()
+            .map(|v| Ok(serde_json::from_slice(&v?)?))
+            .collect()
+    }
+}
+
+#[test]
+fn test_audit_log() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = sled::open(dir.path())?;
+    let audit = AuditLog::new(&Store::new(db.clone()))?;
+    assert!(audit.recent(10)?.is_empty());
+
+    let entry = |i: u64| Entry {
+        time: Utc::now(),
+        operator: QQ(100),
+        group: Some(QQ(1)),
+        plugin: "core".to_string(),
+        action: "reload".to_string(),
+        command: "reload".to_string(),
+        success: i.is_multiple_of(2),
+        result: i.to_string(),
+    };
+    for i in 0..5 {
+        audit.insert(&entry(i))?;
+    }
+    let results = |entries: Vec<Entry>| entries.into_iter().map(|e| e.result).collect::<Vec<_>>();
+    assert_eq!(results(audit.recent(2)?), vec!["4", "3"]);
+    assert_eq!(results(audit.recent(10)?).len(), 5);
+    assert_eq!(results(audit.all()?), vec!["0", "1", "2", "3", "4"]);
+
+    // 记录保存在树中，重新创建后仍然存在。不重新打开目录：sled 后台线程可能还持有文件锁
+    drop(audit);
+    let audit = AuditLog::new(&Store::new(db))?;
+    assert_eq!(results(audit.recent(1)?), vec!["4"]);
+    Ok(())
+}
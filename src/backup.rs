@@ -0,0 +1,138 @@
+//! 数据库导出与导入，见 `avabot db export` 与 `avabot db import`
+//!
+//! 导出全部树为 json，格式为 `{"trees": {"树名": [[键, 值], ...]}}`。是 UTF-8 并且不含控制字符的键和值
+//! 保存为字符串，其余保存为 `{"hex": "..."}`。
+
+use std::collections::BTreeMap;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Bytes {
+    Text(String),
+    Hex { hex: String },
+}
+
+impl Bytes {
+    fn encode(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            // 控制字符虽然是合法的 UTF-8，但多半是二进制数据，比如整数编码的键
+            Ok(s) if !s.chars().any(char::is_control) => Bytes::Text(s.to_string()),
+            _ => Bytes::Hex {
+                hex: bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            },
+        }
+    }
+
+    fn decode(&self) -> Result<Vec<u8>> {
+        match self {
+            Bytes::Text(s) => Ok(s.as_bytes().to_vec()),
+            Bytes::Hex { hex } => {
+                if hex.len() % 2 != 0 {
+                    bail!("hex 长度需要是偶数：{}", hex);
+                }
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        let byte = hex.get(i..i + 2).context("hex 中有非 ASCII 字符")?;
+                        u8::from_str_radix(byte, 16).with_context(|| format!("{} 不是 hex", byte))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dump {
+    pub trees: BTreeMap<String, Vec<(Bytes, Bytes)>>,
+}
+
+/// 导出全部树，包括默认树和记录版本号的树
+pub fn export(db: &sled::Db) -> Result<Dump> {
+    let mut dump = Dump::default();
+    for name in db.tree_names() {
+        let tree = db.open_tree(&name)?;
+        let entries = tree
+            .iter()
+            .map(|kv| {
+                let (k, v) = kv?;
+                Ok((Bytes::encode(&k), Bytes::encode(&v)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if entries.is_empty() {
+            continue;
+        }
+        dump.trees
+            .insert(String::from_utf8_lossy(&name).to_string(), entries);
+    }
+    Ok(dump)
+}
+
+/// 导入，已经存在的键会被覆盖。返回导入的条数
+pub fn import(db: &sled::Db, dump: &Dump) -> Result<usize> {
+    let mut count = 0;
+    for (name, entries) in dump.trees.iter() {
+        let tree = db.open_tree(name)?;
+        for (k, v) in entries {
+            tree.insert(k.decode()?, v.decode()?)?;
+            count += 1;
+        }
+        tree.flush()?;
+        info!("导入 {}：{} 条", name, entries.len());
+    }
+    Ok(count)
+}
+
+#[test]
+fn test_export_import() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = sled::open(dir.path())?;
+    db.insert("A-SOUL", "向晚")?;
+    let tree = db.open_tree("binary")?;
+    tree.insert(42u64.to_be_bytes(), vec![0xff, 0x00])?;
+    crate::migrate::run(&crate::Store::new(db.clone()), crate::migrate::MIGRATIONS)?;
+
+    let dump = export(&db)?;
+    assert_eq!(
+        dump.trees["binary"],
+        vec![(
+            Bytes::Hex {
+                hex: "000000000000002a".to_string()
+            },
+            Bytes::Hex {
+                hex: "ff00".to_string()
+            }
+        )]
+    );
+
+    // 经过 json 后导入另一个数据库
+    let json = serde_json::to_string(&dump)?;
+    let dump: Dump = serde_json::from_str(&json)?;
+    let other_dir = tempfile::tempdir()?;
+    let other = sled::open(other_dir.path())?;
+    assert!(import(&other, &dump)? >= 2);
+
+    assert_eq!(other.get("A-SOUL")?.as_deref(), Some("向晚".as_bytes()));
+    assert_eq!(
+        other
+            .open_tree("binary")?
+            .get(42u64.to_be_bytes())?
+            .as_deref(),
+        Some(&[0xff, 0x00][..])
+    );
+    for migration in crate::migrate::MIGRATIONS {
+        assert_eq!(
+            crate::migrate::version(&crate::Store::new(other.clone()), migration.tree)?,
+            crate::migrate::version(&crate::Store::new(db.clone()), migration.tree)?
+        );
+    }
+
+    assert!(Bytes::Hex {
+        hex: "abc".to_string()
+    }
+    .decode()
+    .is_err());
+    Ok(())
+}
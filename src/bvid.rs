@@ -0,0 +1,206 @@
+//! bilibili 视频的 av 号与 BV 号
+//!
+//! 两者是一一对应的，不需要请求 bilibili 就可以互相转换。[`find`] 从消息或者链接中找出视频，
+//! `av170001`、`BV17x411w7KC` 以及包含两者的链接都可以识别。
+
+use std::fmt;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+const XOR_CODE: u64 = 23442827791579;
+const MAX_AID: u64 = 1 << 51;
+const MASK_CODE: u64 = MAX_AID - 1;
+const BASE: u64 = 58;
+const ALPHABET: &[u8; 58] = b"FcwAPNKTMug3GV5Lj7EJnHpWsx4tb8haYeviqBz6rkCy12mUSDQX9RdoZf";
+/// BV 号的长度，包括开头的 `BV1`
+const BVID_LEN: usize = 12;
+
+/// 一个视频，保存 av 号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Video(u64);
+
+impl Video {
+    /// av 号需要在 `1..2^51` 之间
+    pub fn from_aid(aid: u64) -> Option<Self> {
+        if (1..MAX_AID).contains(&aid) {
+            Some(Self(aid))
+        } else {
+            None
+        }
+    }
+
+    pub fn from_bvid(bvid: &str) -> Option<Self> {
+        let bytes = bvid.as_bytes();
+        if bytes.len() != BVID_LEN || !bvid.starts_with("BV1") {
+            return None;
+        }
+        let mut digits = [0; BVID_LEN];
+        digits.copy_from_slice(bytes);
+        digits.swap(3, 9);
+        digits.swap(4, 7);
+        let mut tmp: u64 = 0;
+        for c in &digits[3..] {
+            let digit = ALPHABET.iter().position(|a| a == c)? as u64;
+            tmp = tmp * BASE + digit;
+        }
+        // 不是由 av 号生成的字符串也能算出结果，转换回去不一致的不是合法的 BV 号
+        let video = Self::from_aid((tmp & MASK_CODE) ^ XOR_CODE)?;
+        if video.bvid() == bvid {
+            Some(video)
+        } else {
+            None
+        }
+    }
+
+    /// 解析 `av123`（不区分大小写）或者 `BV1…`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.get(..2) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("av") => {
+                let digits = &s[2..];
+                if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                Self::from_aid(digits.parse().ok()?)
+            }
+            _ => Self::from_bvid(s),
+        }
+    }
+
+    pub fn aid(self) -> u64 {
+        self.0
+    }
+
+    pub fn bvid(self) -> String {
+        let mut bytes = *b"BV1000000000";
+        let mut tmp = (MAX_AID | self.0) ^ XOR_CODE;
+        let mut i = BVID_LEN - 1;
+        while tmp > 0 {
+            bytes[i] = ALPHABET[(tmp % BASE) as usize];
+            tmp /= BASE;
+            i -= 1;
+        }
+        bytes.swap(3, 9);
+        bytes.swap(4, 7);
+        String::from_utf8(bytes.to_vec()).expect("BV 号只包含 ASCII 字符")
+    }
+}
+
+/// 显示为 BV 号
+impl fmt::Display for Video {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.bvid())
+    }
+}
+
+/// 文本中的一个视频以及它在文本中的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub video: Video,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// 找出 `s` 中全部的 av 号和 BV 号，前后紧挨着字母或数字的不算
+pub fn find_iter(s: &str) -> impl Iterator<Item = Match> + '_ {
+    lazy_static! {
+        static ref VIDEO_REGEX: Regex = Regex::new(r"[aA][vV]\d+|BV1[0-9A-Za-z]{9}").unwrap();
+    }
+    let alphanumeric = |c: Option<char>| matches!(c, Some(c) if c.is_ascii_alphanumeric());
+    VIDEO_REGEX.find_iter(s).filter_map(move |m| {
+        if alphanumeric(s[..m.start()].chars().next_back())
+            || alphanumeric(s[m.end()..].chars().next())
+        {
+            return None;
+        }
+        Some(Match {
+            video: Video::parse(m.as_str())?,
+            start: m.start(),
+            end: m.end(),
+        })
+    })
+}
+
+/// `s` 中的第一个视频
+pub fn find(s: &str) -> Option<Video> {
+    find_iter(s).next().map(|m| m.video)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_known() {
+        let cases = [
+            (170001, "BV17x411w7KC"),
+            (635700727, "BV17b4y1J7ed"),
+            (MAX_AID - 1, "BV1aPPTfmvQq"),
+        ];
+        for (aid, bvid) in cases {
+            let video = Video::from_aid(aid).unwrap();
+            assert_eq!(video.bvid(), bvid);
+            assert_eq!(Video::from_bvid(bvid), Some(video));
+        }
+        assert_eq!(Video::from_aid(0), None);
+        assert_eq!(Video::from_aid(MAX_AID), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let video = Video::from_aid(170001).unwrap();
+        assert_eq!(Video::parse("av170001"), Some(video));
+        assert_eq!(Video::parse("AV170001"), Some(video));
+        assert_eq!(Video::parse("BV17x411w7KC"), Some(video));
+        for s in [
+            "",
+            "av",
+            "av-1",
+            "av0",
+            "av1x",
+            "BV17x411w7K",
+            "BV17x411w7KCC",
+            "BV17x411w7K0",
+            "bv17x411w7KC",
+        ] {
+            assert_eq!(Video::parse(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let aid = |s: &str| find_iter(s).map(|m| m.video.aid()).collect::<Vec<_>>();
+        assert_eq!(aid("看看 av170001 和BV17b4y1J7ed"), vec![170001, 635700727]);
+        assert_eq!(
+            aid("https://www.bilibili.com/video/av635700727?BV17x411w7KC"),
+            vec![635700727, 170001]
+        );
+        assert_eq!(
+            aid("https://m.bilibili.com/video/BV17x411w7KC?p=1"),
+            vec![170001]
+        );
+        assert_eq!(aid("java1 xBV17x411w7KC BV17x411w7KCa"), Vec::<u64>::new());
+        let m = find_iter("封面 av2").next().unwrap();
+        assert_eq!((m.start, m.end), ("封面 ".len(), "封面 av2".len()));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_roundtrip(aid in 1..MAX_AID) {
+            let video = Video::from_aid(aid).unwrap();
+            let bvid = video.bvid();
+            prop_assert_eq!(bvid.len(), BVID_LEN);
+            prop_assert_eq!(Video::from_bvid(&bvid), Some(video));
+            prop_assert_eq!(Video::parse(&format!("av{}", aid)), Some(video));
+            prop_assert_eq!(find(&format!("https://b23.tv/{}?share", bvid)), Some(video));
+        }
+
+        #[test]
+        fn prop_bvid_injective(bvid in "BV1[0-9A-Za-z]{9}") {
+            if let Some(video) = Video::from_bvid(&bvid) {
+                prop_assert_eq!(video.bvid(), bvid);
+            }
+        }
+    }
+}
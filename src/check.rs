@@ -0,0 +1,58 @@
+//! 检查配置文件，见 `avabot check-config`
+
+use std::collections::HashSet;
+
+use crate::config::{describe_error, ConfigSource};
+use crate::plugins;
+
+/// 读取配置并做语义检查，返回发现的问题
+pub fn check_config(source: &ConfigSource) -> Vec<String> {
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![format!(
+                "读取 {} 失败：{}",
+                source.path.display(),
+                describe_error(&e)
+            )]
+        }
+    };
+
+    let mut problems = vec![];
+    let mut names = config.plugins.keys().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        if plugins::find(name).is_none() {
+            problems.push(format!("plugins.{} 不是已知的插件", name));
+        }
+    }
+    let mut accounts = HashSet::new();
+    for account in config.accounts.iter() {
+        if !accounts.insert(account.qq) {
+            problems.push(format!("accounts 中的 QQ {} 重复", account.qq.0));
+        }
+        let mut names = account.plugins.keys().collect::<Vec<_>>();
+        names.sort();
+        for name in names {
+            if plugins::find(name).is_none() {
+                problems.push(format!(
+                    "accounts 中 {} 的 plugins.{} 不是已知的插件",
+                    account.qq.0, name
+                ));
+            }
+        }
+    }
+    let mut rules = config.rate_limit.iter().collect::<Vec<_>>();
+    rules.sort_by_key(|(name, _)| *name);
+    for (name, _) in rules {
+        if plugins::find(name).is_none() {
+            problems.push(format!("rate_limit.{} 不是已知的插件", name));
+        }
+    }
+    problems.extend(config.http.check());
+    problems.extend(config.router.check());
+    problems.extend(config.messages.check());
+    problems.extend(config.keyword_reply.check());
+    problems.extend(config.asoul_weekly.check());
+    problems
+}
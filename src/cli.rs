@@ -0,0 +1,69 @@
+//! 命令行参数
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::config::CONFIG_PATH;
+
+#[derive(Debug, Parser)]
+#[clap(version, about = "A-SOUL 粉丝群 QQ 机器人")]
+pub struct Args {
+    /// 配置文件路径
+    #[clap(long, env = "AVABOT_CONFIG", default_value = CONFIG_PATH)]
+    pub config: PathBuf,
+
+    /// log4rs 配置文件路径
+    #[clap(long, env = "AVABOT_LOG_CONFIG", default_value = "log4rs.yml")]
+    pub log_config: PathBuf,
+
+    /// sled 数据库路径，覆盖配置文件和 AVABOT_DB_PATH
+    #[clap(long)]
+    pub db_path: Option<String>,
+
+    /// 控制台模式：不连接 mirai，把标准输入的每一行作为消息发给机器人
+    #[clap(long)]
+    pub console: bool,
+
+    /// 控制台模式下发送者的 QQ 号
+    #[clap(long, default_value = "10000")]
+    pub console_sender: u64,
+
+    /// 控制台模式下消息所在的群，不指定时作为好友消息
+    #[clap(long)]
+    pub console_group: Option<u64>,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// 检查配置文件，不连接 mirai。有问题时返回非零退出码
+    CheckConfig {
+        /// 配置文件路径，默认与 --config 相同
+        path: Option<PathBuf>,
+    },
+    /// 把审计日志以 json 输出到标准输出。sled 数据库不能同时被两个进程打开，需要先停止机器人
+    ExportAudit {
+        /// 多账号时导出哪个账号的记录，默认为第一个账号
+        #[clap(long)]
+        account: Option<u64>,
+    },
+    /// 导出或导入整个数据库，同样需要先停止机器人
+    Db {
+        #[clap(subcommand)]
+        command: DbCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DbCommand {
+    /// 把全部数据导出为 json
+    Export {
+        /// 输出文件，默认输出到标准输出
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// 从 json 导入，已经存在的键会被覆盖
+    Import { input: PathBuf },
+}
@@ -1,33 +1,562 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
 use miraie::bot::QQ;
-use serde::Deserialize;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Deserialize)]
+pub const CONFIG_PATH: &str = "config.yaml";
+
+/// 修改后需要重启才能生效的配置项
+const RESTART_REQUIRED: &[&str] = &[
+    "qq",
+    "verify_key",
+    "addr",
+    "accounts",
+    "db_path",
+    "plugins",
+    "metrics",
+    "http",
+];
+
+fn default_qq() -> QQ {
+    QQ(0)
+}
+
+/// 一个机器人账号
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub qq: QQ,
+    pub verify_key: String,
+    pub addr: String,
+    /// 覆盖顶层 `plugins` 中的开关
+    #[serde(default)]
+    pub plugins: HashMap<String, bool>,
+    /// 只在这些群里响应，不填时不限制
+    #[serde(default)]
+    pub allow_groups: Option<HashSet<QQ>>,
+}
+
+/// `qq`、`verify_key`、`addr`、`db_path` 可以不写在配置文件中，
+/// 而是通过 `AVABOT_*` 环境变量提供，见 [`Config::apply_env`]。
+/// 配置了 `accounts` 时使用其中的多个账号，不再使用顶层的 `qq`、`verify_key`、`addr`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default = "default_qq")]
     pub qq: QQ,
+    #[serde(default)]
     pub verify_key: String,
+    #[serde(default)]
     pub addr: String,
 
+    /// 多账号
+    #[serde(default)]
+    pub accounts: Vec<Account>,
+
     /// path for sled db
+    #[serde(default)]
     pub db_path: String,
 
     pub admins: HashSet<QQ>,
 
+    #[serde(default)]
+    pub permission: crate::permission::Config,
+
+    #[serde(default)]
+    pub supervisor: crate::supervisor::Config,
+
+    /// 插件开关，未列出的插件默认启用
+    #[serde(default)]
+    pub plugins: HashMap<String, bool>,
+
+    /// 按插件名配置的冷却规则，未列出的插件不限流
+    #[serde(default)]
+    pub rate_limit: HashMap<String, crate::rate_limit::Rule>,
+
+    #[serde(default)]
+    pub error_report: crate::report::Config,
+    /// 监控
+    #[serde(default)]
+    pub metrics: crate::metrics::Config,
+    /// 插件共用的 HTTP 客户端
+    #[serde(default)]
+    pub http: crate::http::Config,
+    /// 命令前缀
+    #[serde(default)]
+    pub router: crate::router::Config,
+    /// 回复文本的语言和按群覆盖的条目
+    #[serde(default)]
+    pub messages: crate::i18n::Config,
+
     pub keyword_reply: crate::plugins::keyword_reply::KeywordReplyConfig,
 
     pub asoul_weekly: crate::plugins::asoul_weekly::Config,
 }
 
 impl Config {
-    /// 从 config.yaml 读
-    pub fn new() -> Result<Self> {
-        let reader = std::fs::File::open("config.yaml")?;
+    /// 测试用的最小配置，各个测试再修改需要的字段
+    #[cfg(test)]
+    pub fn for_test() -> Self {
+        serde_yaml::from_str(
+            r"
+qq: 1
+verify_key: key
+addr: localhost
+db_path: db
+admins: []
+keyword_reply: {}
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        )
+        .unwrap()
+    }
+
+    /// 只读取配置文件，不应用环境变量，也不检查必填项
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let reader = std::fs::File::open(path)?;
         let config = serde_yaml::from_reader(reader)?;
         Ok(config)
     }
 
+    /// 用 `AVABOT_QQ`、`AVABOT_VERIFY_KEY`、`AVABOT_ADDR`、`AVABOT_DB_PATH` 覆盖配置
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<()> {
+        if let Some(qq) = var("AVABOT_QQ") {
+            self.qq = QQ(qq.trim().parse().context("AVABOT_QQ 不是合法的 QQ 号")?);
+        }
+        if let Some(verify_key) = var("AVABOT_VERIFY_KEY") {
+            self.verify_key = verify_key;
+        }
+        if let Some(addr) = var("AVABOT_ADDR") {
+            self.addr = addr;
+        }
+        if let Some(db_path) = var("AVABOT_DB_PATH") {
+            self.db_path = db_path;
+        }
+        Ok(())
+    }
+
+    /// 检查必填项是否都已经通过配置文件或者环境变量提供
+    fn check_required(&self) -> Result<()> {
+        let single = self.accounts.is_empty();
+        let missing = [
+            ("qq", "AVABOT_QQ", single && self.qq.0 == 0),
+            (
+                "verify_key",
+                "AVABOT_VERIFY_KEY",
+                single && self.verify_key.is_empty(),
+            ),
+            ("addr", "AVABOT_ADDR", single && self.addr.is_empty()),
+            ("db_path", "AVABOT_DB_PATH", self.db_path.is_empty()),
+        ];
+        for (key, env, missing) in missing {
+            if missing {
+                bail!(
+                    "缺少配置项 {}，请在配置文件中设置或者使用环境变量 {}",
+                    key,
+                    env
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// 检查冷却规则，不合法的规则会让令牌桶算出错误的等待时间
+    fn check_rate_limit(&self) -> Result<()> {
+        let mut rules = self.rate_limit.iter().collect::<Vec<_>>();
+        rules.sort_by_key(|(name, _)| *name);
+        let problems = rules
+            .into_iter()
+            .flat_map(|(name, rule)| rule.check(name))
+            .collect::<Vec<_>>();
+        if !problems.is_empty() {
+            bail!("冷却规则不合法：{}", problems.join("；"));
+        }
+        Ok(())
+    }
+
+    /// 控制台模式下用占位值补上连接 mirai 所需的配置项
+    fn fill_console_defaults(&mut self) {
+        if self.qq.0 == 0 {
+            self.qq = QQ(1);
+        }
+        if self.verify_key.is_empty() {
+            self.verify_key = "console".to_string();
+        }
+        if self.addr.is_empty() {
+            self.addr = "console".to_string();
+        }
+    }
+
     pub fn is_admin(&self, qq: QQ) -> bool {
         self.admins.contains(&qq)
     }
+
+    pub fn plugin_enabled(&self, name: &str) -> bool {
+        self.plugins.get(name).copied().unwrap_or(true)
+    }
+
+    /// 所有账号，没有配置 `accounts` 时为顶层的单个账号
+    pub fn accounts(&self) -> Vec<Account> {
+        if !self.accounts.is_empty() {
+            return self.accounts.clone();
+        }
+        vec![Account {
+            qq: self.qq,
+            verify_key: self.verify_key.clone(),
+            addr: self.addr.clone(),
+            plugins: HashMap::new(),
+            allow_groups: None,
+        }]
+    }
+
+    fn account(&self, qq: QQ) -> Option<&Account> {
+        self.accounts.iter().find(|a| a.qq == qq)
+    }
+
+    /// 插件对账号 `account` 是否启用，账号没有单独设置时使用顶层的开关
+    pub fn plugin_enabled_for(&self, account: QQ, name: &str) -> bool {
+        self.account(account)
+            .and_then(|a| a.plugins.get(name).copied())
+            .unwrap_or_else(|| self.plugin_enabled(name))
+    }
+
+    /// 账号 `account` 是否在群 `group` 里响应
+    pub fn group_allowed(&self, account: QQ, group: QQ) -> bool {
+        match self.account(account).and_then(|a| a.allow_groups.as_ref()) {
+            Some(groups) => groups.contains(&group),
+            None => true,
+        }
+    }
+
+    /// 与新配置相比发生变化的配置项，只包含路径，不包含值
+    pub fn diff(&self, new: &Config) -> Result<Vec<String>> {
+        let old = serde_json::to_value(self)?;
+        let new = serde_json::to_value(new)?;
+        let mut changes = vec![];
+        diff_value("", &old, &new, &mut changes);
+        for change in changes.iter_mut() {
+            let key = change[2..].split('.').next().unwrap_or_default();
+            if RESTART_REQUIRED.contains(&key) {
+                change.push_str("（需要重启生效）");
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// 配置读取失败的原因，YAML 有误时带上行列号
+pub fn describe_error(e: &anyhow::Error) -> String {
+    match e.downcast_ref::<serde_yaml::Error>() {
+        Some(yaml) => match yaml.location() {
+            Some(location) => format!(
+                "第 {} 行第 {} 列：{}",
+                location.line(),
+                location.column(),
+                yaml
+            ),
+            None => yaml.to_string(),
+        },
+        None => format!("{:?}", e),
+    }
+}
+
+/// 比较两个 json，新增记为 `+ path`，删除记为 `- path`，修改记为 `~ path`
+fn diff_value(path: &str, old: &Value, new: &Value, changes: &mut Vec<String>) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                match new.get(key) {
+                    Some(new_value) => diff_value(&join(key), old_value, new_value, changes),
+                    None => changes.push(format!("- {}", join(key))),
+                }
+            }
+            for key in new.keys().filter(|k| !old.contains_key(*k)) {
+                changes.push(format!("+ {}", join(key)));
+            }
+        }
+        // HashSet 序列化之后的顺序不固定，排序后再比较
+        (Value::Array(old), Value::Array(new)) => {
+            let sorted = |v: &Vec<Value>| {
+                let mut v = v.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+                v.sort();
+                v
+            };
+            if sorted(old) != sorted(new) {
+                changes.push(format!("~ {}", path));
+            }
+        }
+        (old, new) => {
+            if old != new {
+                changes.push(format!("~ {}", path));
+            }
+        }
+    }
+}
+
+/// 配置从哪里读：配置文件路径，以及命令行上指定的覆盖项
+///
+/// 优先级为命令行 > 环境变量 > 配置文件
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    pub db_path: Option<String>,
+    /// 控制台模式不连接 mirai，`qq`、`verify_key`、`addr` 可以不填
+    pub console: bool,
+}
+
+impl Default for ConfigSource {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from(CONFIG_PATH),
+            db_path: None,
+            console: false,
+        }
+    }
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config> {
+        let mut config = Config::from_path(&self.path)?;
+        config.messages.load_catalogues()?;
+        config.apply_env(|key| std::env::var(key).ok())?;
+        if let Some(db_path) = &self.db_path {
+            config.db_path = db_path.clone();
+        }
+        if self.console {
+            config.fill_console_defaults();
+        }
+        config.check_required()?;
+        config.check_rate_limit()?;
+        Ok(config)
+    }
+}
+
+/// 可以原子替换的配置，所有插件共享同一份
+#[derive(Clone)]
+pub struct SharedConfig {
+    config: Arc<ArcSwap<Config>>,
+    source: Arc<ConfigSource>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        Self::with_source(config, ConfigSource::default())
+    }
+
+    pub fn with_source(config: Config, source: ConfigSource) -> Self {
+        Self {
+            config: Arc::new(ArcSwap::from_pointee(config)),
+            source: Arc::new(source),
+        }
+    }
+
+    /// 从配置来源读取
+    pub fn load_from(source: ConfigSource) -> Result<Self> {
+        let config = source.load()?;
+        Ok(Self::with_source(config, source))
+    }
+
+    pub fn source(&self) -> &ConfigSource {
+        &self.source
+    }
+
+    /// 当前配置
+    pub fn load(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+
+    /// 重新读取配置文件，读取失败时保留原配置。返回发生变化的配置项
+    pub fn reload(&self) -> Result<Vec<String>> {
+        let new = self.source.load()?;
+        let changes = self.load().diff(&new)?;
+        self.config.store(Arc::new(new));
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Config {
+        serde_yaml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_diff() -> Result<()> {
+        let old = parse(
+            r"
+qq: 1
+verify_key: key
+addr: localhost
+db_path: db
+admins: [100, 200, 300]
+keyword_reply:
+    full_match:
+        a: '1'
+        b: '2'
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        );
+        assert!(old.diff(&old.clone())?.is_empty());
+
+        let new = parse(
+            r"
+qq: 1
+verify_key: another key
+addr: localhost
+db_path: db
+admins: [300, 200, 100]
+keyword_reply:
+    full_match:
+        a: '2'
+        c: '3'
+asoul_weekly:
+    url: http://localhost
+    allow_groups: [1]
+",
+        );
+        let mut changes = old.diff(&new)?;
+        changes.sort();
+        assert_eq!(
+            changes,
+            vec![
+                "+ keyword_reply.full_match.c",
+                "- keyword_reply.full_match.b",
+                "~ asoul_weekly.allow_groups",
+                "~ keyword_reply.full_match.a",
+                "~ verify_key（需要重启生效）",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_error() {
+        let e = serde_yaml::from_str::<Config>("qq: 1\nverify_key: [\n")
+            .map_err(anyhow::Error::from)
+            .unwrap_err();
+        assert!(describe_error(&e).starts_with("第 3 行"));
+
+        let e = anyhow::anyhow!("file not found");
+        assert_eq!(describe_error(&e), "file not found");
+    }
+
+    #[test]
+    fn test_apply_env() -> Result<()> {
+        let mut config = parse(
+            r"
+addr: localhost
+db_path: db
+admins: []
+keyword_reply: {}
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        );
+        assert!(config.check_required().is_err());
+
+        let env = |key: &str| match key {
+            "AVABOT_QQ" => Some("123".to_string()),
+            "AVABOT_VERIFY_KEY" => Some("secret".to_string()),
+            "AVABOT_ADDR" => Some("127.0.0.1:8080".to_string()),
+            _ => None,
+        };
+        config.apply_env(env)?;
+        config.check_required()?;
+        assert_eq!(config.qq, QQ(123));
+        assert_eq!(config.verify_key, "secret");
+        assert_eq!(config.addr, "127.0.0.1:8080");
+        assert_eq!(config.db_path, "db");
+
+        assert!(config
+            .apply_env(|key| (key == "AVABOT_QQ").then(|| "abc".to_string()))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_rate_limit() {
+        let config = |refill_seconds: &str| {
+            let mut config = Config::for_test();
+            let rules = format!("fraud: {{capacity: 1, refill_seconds: {}}}", refill_seconds);
+            config.rate_limit = serde_yaml::from_str(&rules).unwrap();
+            config
+        };
+        assert!(config("60").check_rate_limit().is_ok());
+        assert!(config("-1").check_rate_limit().is_err());
+        assert!(config(".nan").check_rate_limit().is_err());
+    }
+
+    #[test]
+    fn test_accounts() -> Result<()> {
+        let config = parse(
+            r"
+accounts:
+  - qq: 1
+    verify_key: a
+    addr: localhost:8080
+    plugins:
+        fraud: true
+    allow_groups: [10]
+  - qq: 2
+    verify_key: b
+    addr: localhost:8081
+db_path: db
+admins: []
+plugins:
+    fraud: false
+keyword_reply: {}
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        );
+        config.check_required()?;
+        let accounts = config.accounts();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[1].addr, "localhost:8081");
+
+        assert!(config.plugin_enabled_for(QQ(1), "fraud"));
+        assert!(!config.plugin_enabled_for(QQ(2), "fraud"));
+        assert!(config.plugin_enabled_for(QQ(2), "shab"));
+        assert!(config.group_allowed(QQ(1), QQ(10)));
+        assert!(!config.group_allowed(QQ(1), QQ(11)));
+        assert!(config.group_allowed(QQ(2), QQ(11)));
+
+        let single = parse(
+            r"
+qq: 3
+verify_key: c
+addr: localhost
+db_path: db
+admins: []
+keyword_reply: {}
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        );
+        let accounts = single.accounts();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].qq, QQ(3));
+        assert!(single.group_allowed(QQ(3), QQ(11)));
+        Ok(())
+    }
 }
@@ -0,0 +1,175 @@
+//! 本地控制台模式
+//!
+//! 不连接真实的 mirai，而是连接进程内的 [`MockMirai`]：标准输入的每一行作为一条消息发给机器人，
+//! 机器人的回复打印到标准输出。用于在本地试用关键词回复、A-SOUL 周报等插件。
+//!
+//! miraie 的 `Bot` 只能通过 `Bot::new` 连接 mirai-api-http 创建，消息分发和回复都经过这个连接，
+//! 没有公开的接口直接投递消息或截获回复，所以这里不绕过连接调用 handler，而是让 `Bot` 连接本机回环地址上的
+//! [`MockMirai`]。这样插件的注册、`Data` 注入和消息分发都与正常运行时一致，`tests/plugins.rs` 也是同样的做法。
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::mock::{at, plain, Call, MockMirai};
+use crate::prelude::*;
+use crate::supervisor::Supervisor;
+
+/// 标准输入结束后，机器人这么久没有动作就退出
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// 等待输入时检查机器人动作的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 模拟的发送者
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    pub sender: QQ,
+    /// 为空时发送好友消息
+    pub group: Option<QQ>,
+}
+
+pub async fn run(supervisor: &Supervisor, options: Options) -> Result<()> {
+    let mirai = MockMirai::start().await?;
+    let account = supervisor.account();
+    let (bot, con) = miraie::Bot::new(&mirai.addr(), &account.verify_key, account.qq).await?;
+    supervisor.setup(bot);
+    tokio::spawn(con.run());
+    mirai.wait_connected().await?;
+
+    match options.group {
+        Some(group) => eprintln!(
+            "控制台模式：以 {} 的身份在群 {} 中发言，@QQ 号表示 @ 某人",
+            options.sender.0, group.0
+        ),
+        None => eprintln!(
+            "控制台模式：以 {} 的身份私聊机器人，@QQ 号表示 @ 某人",
+            options.sender.0
+        ),
+    }
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut eof = false;
+    loop {
+        let timeout = if eof { IDLE_TIMEOUT } else { POLL_INTERVAL };
+        tokio::select! {
+            line = lines.next_line(), if !eof => match line? {
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => {
+                    let chain = parse_line(&line);
+                    match options.group {
+                        Some(group) => mirai.group_message(group.0, options.sender.0, chain),
+                        None => mirai.friend_message(options.sender.0, chain),
+                    };
+                }
+                None => eof = true,
+            },
+            call = mirai.next_call(timeout) => match call {
+                Some(call) => {
+                    if let Some(s) = render(&call) {
+                        println!("{}", s);
+                    }
+                }
+                None if eof => break,
+                None => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+/// 把一行输入转成消息链，`@123` 转成 @ 消息块
+fn parse_line(line: &str) -> Vec<Value> {
+    lazy_static! {
+        static ref AT: Regex = Regex::new(r"@(\d+)").unwrap();
+    }
+    let mut chain = vec![];
+    let mut last = 0;
+    for cap in AT.captures_iter(line) {
+        let m = cap.get(0).unwrap();
+        if m.start() > last {
+            chain.push(plain(&line[last..m.start()]));
+        }
+        chain.push(at(cap[1].parse().unwrap_or_default()));
+        last = m.end();
+    }
+    if last < line.len() {
+        chain.push(plain(&line[last..]));
+    }
+    chain
+}
+
+/// 机器人的调用转成可读的文本，不需要显示的调用返回 `None`
+fn render(call: &Call) -> Option<String> {
+    match call.command.as_str() {
+        "sendGroupMessage" | "sendFriendMessage" | "sendTempMessage" => {
+            let text = call
+                .message_chain()
+                .iter()
+                .filter_map(render_block)
+                .collect::<String>();
+            Some(format!("机器人：{}", text))
+        }
+        "recall" => Some("（机器人撤回了一条消息）".to_string()),
+        _ => None,
+    }
+}
+
+fn render_block(block: &Value) -> Option<String> {
+    // 图片、语音优先显示链接，其次是本地路径和 id
+    let source = |keys: &[&str]| {
+        keys.iter()
+            .filter_map(|key| block[*key].as_str())
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    let s = match block["type"].as_str()? {
+        "Plain" => block["text"].as_str()?.to_string(),
+        "At" => format!("@{}", block["target"]),
+        "AtAll" => "@全体成员".to_string(),
+        "Face" => format!("[表情 {}]", source(&["name", "faceId"])),
+        "Image" | "FlashImage" => format!("[图片 {}]", source(&["url", "path", "imageId"])),
+        "Voice" => format!("[语音 {}]", source(&["url", "path", "voiceId"])),
+        "Quote" | "Source" => return None,
+        other => format!("[{}]", other),
+    };
+    Some(s)
+}
+
+#[test]
+fn test_parse_line() {
+    assert_eq!(parse_line("你好"), vec![plain("你好")]);
+    assert_eq!(
+        parse_line("设置权限 @123 信任"),
+        vec![plain("设置权限 "), at(123), plain(" 信任")]
+    );
+    assert_eq!(parse_line("@1@2"), vec![at(1), at(2)]);
+}
+
+#[test]
+fn test_render() {
+    let call = Call {
+        command: "sendGroupMessage".to_string(),
+        sub_command: None,
+        content: serde_json::json!({
+            "target": 1,
+            "messageChain": [
+                { "type": "At", "target": 123, "display": "" },
+                { "type": "Plain", "text": " 你好" },
+                { "type": "Image", "imageId": null, "url": null, "path": "a.jpg" },
+                { "type": "Voice", "voiceId": "abc", "url": null, "path": null },
+            ],
+        }),
+    };
+    assert_eq!(
+        render(&call).unwrap(),
+        "机器人：@123 你好[图片 a.jpg][语音 abc]"
+    );
+    let call = Call {
+        command: "about".to_string(),
+        sub_command: None,
+        content: Value::Null,
+    };
+    assert_eq!(render(&call), None);
+}
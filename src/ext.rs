@@ -0,0 +1,35 @@
+//! miraie 消息类型的扩展
+
+use miraie::messages::group::Permission;
+use miraie::prelude::*;
+
+pub trait ConversationExt {
+    /// 消息所在的群，私聊为 `None`
+    fn group_id(&self) -> Option<QQ>;
+
+    /// 发送者是否为 QQ 群主或管理员
+    fn is_group_manager(&self) -> bool;
+}
+
+impl ConversationExt for GroupMessage {
+    fn group_id(&self) -> Option<QQ> {
+        Some(self.sender.group.id)
+    }
+
+    fn is_group_manager(&self) -> bool {
+        matches!(
+            self.sender.permission,
+            Permission::Owner | Permission::Administrator
+        )
+    }
+}
+
+impl ConversationExt for FriendMessage {
+    fn group_id(&self) -> Option<QQ> {
+        None
+    }
+
+    fn is_group_manager(&self) -> bool {
+        false
+    }
+}
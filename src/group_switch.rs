@@ -0,0 +1,74 @@
+//! 按群开关插件
+//!
+//! 开关保存在 sled 的 `group_switch` tree 中，键为 `群号/插件名`。没有记录的插件默认启用。
+
+use anyhow::Result;
+use miraie::prelude::*;
+
+use crate::ext::ConversationExt;
+use crate::Store;
+
+static TREE: &str = "group_switch";
+
+#[derive(Clone)]
+pub struct GroupSwitch {
+    tree: sled::Tree,
+}
+
+impl GroupSwitch {
+    pub fn new(store: &Store) -> Result<Self> {
+        Ok(Self {
+            tree: store.tree(TREE)?,
+        })
+    }
+
+    fn key(group: QQ, plugin: &str) -> String {
+        format!("{}/{}", group.0, plugin)
+    }
+
+    /// 插件在群内是否启用，读取失败时视为启用
+    pub fn is_enabled(&self, group: QQ, plugin: &str) -> bool {
+        match self.tree.get(Self::key(group, plugin)) {
+            Ok(Some(v)) => v.as_ref() != b"0",
+            Ok(None) => true,
+            Err(e) => {
+                error!("读取群 {} 插件 {} 开关失败：{:?}", group.0, plugin, e);
+                true
+            }
+        }
+    }
+
+    /// 消息是否应该交给插件处理，私聊消息总是允许
+    pub fn allows<T: ConversationExt>(&self, msg: &T, plugin: &str) -> bool {
+        match msg.group_id() {
+            Some(group) => self.is_enabled(group, plugin),
+            None => true,
+        }
+    }
+
+    pub fn set(&self, group: QQ, plugin: &str, enabled: bool) -> Result<()> {
+        let value = if enabled { "1" } else { "0" };
+        self.tree.insert(Self::key(group, plugin), value)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_group_switch() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = sled::open(dir.path())?;
+    let switch = GroupSwitch::new(&Store::new(db))?;
+
+    assert!(switch.is_enabled(QQ(1), "fraud"));
+
+    switch.set(QQ(1), "fraud", false)?;
+    assert!(!switch.is_enabled(QQ(1), "fraud"));
+    assert!(switch.is_enabled(QQ(2), "fraud"));
+    assert!(switch.is_enabled(QQ(1), "shab"));
+
+    switch.set(QQ(1), "fraud", true)?;
+    assert!(switch.is_enabled(QQ(1), "fraud"));
+
+    Ok(())
+}
@@ -0,0 +1,232 @@
+//! 共享的 HTTP 客户端
+//!
+//! 启动时按 `config.yaml` 中的 `http` 创建一次，所有账号、所有插件共用同一个连接池，
+//! 以 `Data<HttpClient>` 提供给插件。GET 请求超时、连接失败或者返回 5xx、429 时按指数退避重试。
+
+use reqwest::{Client, IntoUrl, Response, StatusCode};
+
+use crate::prelude::*;
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+fn default_user_agent() -> String {
+    format!("avabot/{}", env!("CARGO_PKG_VERSION"))
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    500
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 整个请求的超时时间
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    /// 代理，如 `http://127.0.0.1:7890`
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// GET 请求失败后的重试次数
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// 第一次重试前等待的毫秒数，之后每次翻倍
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: default_timeout_seconds(),
+            user_agent: default_user_agent(),
+            proxy: None,
+            retries: default_retries(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+        }
+    }
+}
+
+impl Config {
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if self.timeout_seconds == 0 {
+            problems.push("http.timeout_seconds 不能为 0".to_string());
+        }
+        if let Some(proxy) = &self.proxy {
+            if let Err(e) = reqwest::Proxy::all(proxy) {
+                problems.push(format!("http.proxy 不合法：{}", e));
+            }
+        }
+        problems
+    }
+
+    /// 第 `attempt` 次重试前等待的时间
+    fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(1 << attempt.min(16)))
+    }
+}
+
+/// 值得重试的状态码
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[derive(Clone)]
+pub struct HttpClient {
+    client: Client,
+    /// 不跟随重定向，用于解析 b23.tv 短链
+    no_redirect: Client,
+    config: Config,
+}
+
+impl HttpClient {
+    pub fn new(config: &Config) -> Result<Self> {
+        let build = |redirect: reqwest::redirect::Policy| -> Result<Client> {
+            let mut builder = Client::builder()
+                .timeout(Duration::from_secs(config.timeout_seconds))
+                .user_agent(&config.user_agent)
+                // 与 biliapi 自带的客户端一致，bilibili 的部分接口需要 buvid 等 cookie
+                .cookie_store(true)
+                .redirect(redirect);
+            if let Some(proxy) = &config.proxy {
+                builder = builder.proxy(reqwest::Proxy::all(proxy).context("代理地址不合法")?);
+            }
+            Ok(builder.build()?)
+        };
+        Ok(Self {
+            client: build(reqwest::redirect::Policy::default())?,
+            no_redirect: build(reqwest::redirect::Policy::none())?,
+            config: config.clone(),
+        })
+    }
+
+    /// 用于 POST 等不能重试的请求，以及需要传入 `reqwest::Client` 的 biliapi
+    ///
+    /// biliapi 的请求（如 `VideoInfo::request`）虽然是 GET，但由 biliapi 直接发送，不经过
+    /// [`HttpClient::get`]，因此不会重试
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn no_redirect(&self) -> &Client {
+        &self.no_redirect
+    }
+
+    /// GET 请求，超时、连接失败或者返回 5xx、429 时重试。重试用完后返回最后一次的结果
+    pub async fn get(&self, url: impl IntoUrl) -> reqwest::Result<Response> {
+        let url = url.into_url()?;
+        let mut attempt = 0;
+        loop {
+            let result = self.client.get(url.clone()).send().await;
+            let retry = match &result {
+                Ok(response) => retryable(response.status()),
+                // URL 不合法等构造请求时的错误重试也不会成功
+                Err(e) => e.is_timeout() || e.is_connect(),
+            };
+            if !retry || attempt >= self.config.retries {
+                return result;
+            }
+            let delay = self.config.backoff(attempt);
+            match &result {
+                Ok(response) => warn!(
+                    "GET {} 返回 {}，{} 毫秒后重试",
+                    url,
+                    response.status(),
+                    delay.as_millis()
+                ),
+                Err(e) => warn!("GET {} 失败：{}，{} 毫秒后重试", url, e, delay.as_millis()),
+            }
+            attempt += 1;
+            sleep(delay).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// 依次返回 `statuses` 中的状态码，返回收到的请求数
+    async fn serve(statuses: Vec<u16>) -> (String, tokio::task::JoinHandle<usize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let mut count = 0;
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await.unwrap();
+                count += 1;
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                    status
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            count
+        });
+        (url, handle)
+    }
+
+    fn client(retries: u32) -> HttpClient {
+        HttpClient::new(&Config {
+            retries,
+            retry_backoff_ms: 1,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let (url, handle) = serve(vec![503, 429, 200]).await;
+        let response = client(2).get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(handle.await.unwrap(), 3);
+
+        let (url, handle) = serve(vec![500, 502]).await;
+        let response = client(1).get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(handle.await.unwrap(), 2);
+
+        // 4xx 不重试
+        let (url, handle) = serve(vec![404]).await;
+        let response = client(2).get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(handle.await.unwrap(), 1);
+
+        // 构造请求时的错误不重试，否则要等一分钟
+        let client = HttpClient::new(&Config {
+            retry_backoff_ms: 60_000,
+            ..Default::default()
+        })
+        .unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(5), client.get("ftp://localhost/"));
+        assert!(result.await.unwrap().unwrap_err().is_builder());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Config::default();
+        assert_eq!(config.backoff(0), Duration::from_millis(500));
+        assert_eq!(config.backoff(2), Duration::from_millis(2000));
+        assert!(config.check().is_empty());
+
+        let config = Config {
+            timeout_seconds: 0,
+            proxy: Some("not a proxy".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(config.check().len(), 2);
+    }
+}
@@ -0,0 +1,392 @@
+//! 回复文本
+//!
+//! 插件回复的文本不直接写在代码里，而是用 [`Text`] 指定目录（`messages/*.yaml`）中的键和占位符的值，
+//! 回复前按群选择语言并替换占位符。内置的目录编译进程序，`config.yaml` 的 `messages` 可以：
+//!
+//! - 设置默认语言和每个群的语言
+//! - 用 `catalogues` 加载自定义的目录文件，新增语言或者覆盖内置的条目
+//! - 按群覆盖单独的条目，修改机器人的说法
+//!
+//! 查找顺序为：群覆盖的条目 > 群的语言 > 默认语言 > 内置中文，都没有时返回键本身。
+
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::prelude::*;
+
+/// 一个语言的全部条目
+pub type Catalogue = HashMap<String, String>;
+
+/// 内置的目录
+const BUILTIN_SOURCES: &[(&str, &str)] = &[
+    ("zh", include_str!("../messages/zh.yaml")),
+    ("en", include_str!("../messages/en.yaml")),
+];
+
+/// 内置目录缺少条目时使用的语言
+const FALLBACK: &str = "zh";
+
+lazy_static::lazy_static! {
+    static ref BUILTIN: HashMap<&'static str, Catalogue> = BUILTIN_SOURCES
+        .iter()
+        .map(|(name, source)| (*name, serde_yaml::from_str(source).expect("内置目录格式有误")))
+        .collect();
+}
+
+/// 目录中的键以及占位符的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    key: Cow<'static, str>,
+    args: Vec<(&'static str, Arg)>,
+}
+
+/// 占位符的值
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Arg {
+    Plain(String),
+    /// 与外层使用同一语言渲染的文本
+    Text(Text),
+}
+
+impl Text {
+    pub fn new(key: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            key: key.into(),
+            args: vec![],
+        }
+    }
+
+    /// 设置占位符 `{name}` 的值
+    pub fn arg(mut self, name: &'static str, value: impl fmt::Display) -> Self {
+        self.args.push((name, Arg::Plain(value.to_string())));
+        self
+    }
+
+    /// 设置占位符 `{name}` 的值为另一条文本，渲染时使用相同的语言
+    pub fn arg_text(mut self, name: &'static str, text: Text) -> Self {
+        self.args.push((name, Arg::Text(text)));
+        self
+    }
+}
+
+impl From<&'static str> for Text {
+    fn from(key: &'static str) -> Self {
+        Self::new(key)
+    }
+}
+
+/// 使用内置中文，用于日志和错误链
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&Config::default().for_group(None).get(self.clone()))
+    }
+}
+
+fn default_locale() -> String {
+    FALLBACK.to_string()
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupConfig {
+    /// 本群使用的语言，不填时使用默认语言
+    #[serde(default)]
+    pub locale: Option<String>,
+    /// 覆盖的条目
+    #[serde(default)]
+    pub overrides: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 默认语言，内置 zh、en
+    #[serde(default = "default_locale")]
+    pub locale: String,
+    /// 自定义目录文件，语言名 → 路径。与内置语言同名时覆盖其中的条目
+    #[serde(default)]
+    pub catalogues: HashMap<String, PathBuf>,
+    /// 按群号设置
+    #[serde(default)]
+    pub groups: HashMap<u64, GroupConfig>,
+    /// 从 `catalogues` 读取的目录，见 [`Config::load_catalogues`]
+    #[serde(skip)]
+    loaded: HashMap<String, Catalogue>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            locale: default_locale(),
+            catalogues: HashMap::new(),
+            groups: HashMap::new(),
+            loaded: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 默认语言为 `locale`，其他设置为空
+    pub fn with_locale(locale: &str) -> Self {
+        Self {
+            locale: locale.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// 读取 `catalogues` 中的目录文件
+    pub fn load_catalogues(&mut self) -> Result<()> {
+        let mut loaded = HashMap::new();
+        for (name, path) in self.catalogues.iter() {
+            let reader = std::fs::File::open(path)
+                .with_context(|| format!("打开目录文件 {} 失败", path.display()))?;
+            let catalogue: Catalogue = serde_yaml::from_reader(reader)
+                .with_context(|| format!("目录文件 {} 格式有误", path.display()))?;
+            loaded.insert(name.clone(), catalogue);
+        }
+        self.loaded = loaded;
+        Ok(())
+    }
+
+    fn has_locale(&self, locale: &str) -> bool {
+        BUILTIN.contains_key(locale) || self.catalogues.contains_key(locale)
+    }
+
+    fn lookup(&self, locale: &str, key: &str) -> Option<&str> {
+        self.loaded
+            .get(locale)
+            .and_then(|c| c.get(key))
+            .or_else(|| BUILTIN.get(locale).and_then(|c| c.get(key)))
+            .map(|s| s.as_str())
+    }
+
+    /// 群 `group` 中使用的文本，私聊时为 `None`
+    pub fn for_group(&self, group: Option<QQ>) -> Messages<'_> {
+        Messages {
+            config: self,
+            group: group.and_then(|g| self.groups.get(&g.0)),
+        }
+    }
+
+    /// 检查语言是否存在，覆盖的条目是否有对应的键、占位符是否正确
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = vec![];
+        if !self.has_locale(&self.locale) {
+            problems.push(format!("messages.locale 中的语言 {} 不存在", self.locale));
+        }
+        let mut groups = self.groups.iter().collect::<Vec<_>>();
+        groups.sort_by_key(|(group, _)| **group);
+        for (group, config) in groups {
+            if let Some(locale) = &config.locale {
+                if !self.has_locale(locale) {
+                    problems.push(format!(
+                        "messages.groups.{} 的语言 {} 不存在",
+                        group, locale
+                    ));
+                }
+            }
+            let path = format!("messages.groups.{}.overrides", group);
+            problems.extend(check_entries(&path, &config.overrides));
+        }
+        let mut loaded = self.loaded.iter().collect::<Vec<_>>();
+        loaded.sort_by_key(|(name, _)| *name);
+        for (name, catalogue) in loaded {
+            problems.extend(check_entries(&format!("目录 {}", name), catalogue));
+        }
+        problems
+    }
+}
+
+/// 检查条目的键存在于内置目录中，并且没有使用未知的占位符
+fn check_entries(path: &str, entries: &Catalogue) -> Vec<String> {
+    let mut problems = vec![];
+    let mut keys = entries.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        let builtin = match BUILTIN[FALLBACK].get(key) {
+            Some(builtin) => builtin,
+            None => {
+                problems.push(format!("{} 中的 {} 不是已知的键", path, key));
+                continue;
+            }
+        };
+        let known = placeholders(builtin);
+        for name in placeholders(&entries[key]) {
+            if !known.contains(name) {
+                problems.push(format!("{} 中 {} 的占位符 {{{}}} 不存在", path, key, name));
+            }
+        }
+    }
+    problems
+}
+
+/// 模板中的占位符
+fn placeholders(template: &str) -> BTreeSet<&str> {
+    let mut names = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        if let Some(end) = rest.find('}') {
+            names.insert(&rest[..end]);
+            rest = &rest[end + 1..];
+        }
+    }
+    names
+}
+
+/// 把模板中的 `{name}` 替换为对应的值，没有提供值的占位符保持原样
+fn render(template: &str, args: &[(&'static str, String)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            let value = args.iter().find(|(n, _)| *n == name)?;
+            Some((&value.1, end))
+        });
+        match value {
+            Some((value, end)) => {
+                out.push_str(value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// 某个群使用的文本
+pub struct Messages<'a> {
+    config: &'a Config,
+    group: Option<&'a GroupConfig>,
+}
+
+impl<'a> Messages<'a> {
+    fn template<'k>(&'k self, key: &'k str) -> &'k str {
+        if let Some(text) = self.group.and_then(|g| g.overrides.get(key)) {
+            return text;
+        }
+        let locale = self
+            .group
+            .and_then(|g| g.locale.as_deref())
+            .unwrap_or(&self.config.locale);
+        self.config
+            .lookup(locale, key)
+            .or_else(|| self.config.lookup(FALLBACK, key))
+            .unwrap_or(key)
+    }
+
+    pub fn get(&self, text: impl Into<Text>) -> String {
+        let text = text.into();
+        let args = text
+            .args
+            .into_iter()
+            .map(|(name, arg)| match arg {
+                Arg::Plain(value) => (name, value),
+                Arg::Text(text) => (name, self.get(text)),
+            })
+            .collect::<Vec<_>>();
+        render(self.template(&text.key), &args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let zh = &BUILTIN["zh"];
+        for (name, catalogue) in BUILTIN.iter() {
+            for (key, template) in zh.iter() {
+                let translated = catalogue
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{} 缺少 {}", name, key));
+                assert_eq!(placeholders(translated), placeholders(template), "{}", key);
+            }
+            assert_eq!(catalogue.len(), zh.len(), "{} 有多余的键", name);
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let text = Text::new("core.role_set")
+            .arg("target", 123)
+            .arg("role", "信任");
+        assert_eq!(text.to_string(), "已将 123 设置为【信任】");
+        assert_eq!(render("{a}{b}{", &[("a", "{b}".to_string())]), "{b}{b}{");
+        assert_eq!(Text::new("没有这个键").to_string(), "没有这个键");
+        let usage = Text::new("router.usage").arg_text(
+            "usage",
+            Text::new("help.command_args")
+                .arg("command", "封面")
+                .arg_text("args", Text::new("help.args.封面")),
+        );
+        assert_eq!(
+            Config::with_locale("en").for_group(None).get(usage),
+            "Usage: 封面 <video>..."
+        );
+        assert_eq!(
+            placeholders("{a} {b}} {").into_iter().collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_groups() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("fan.yaml");
+        std::fs::write(&path, "core.pong: 在呢\nnot.a.key: x\n")?;
+
+        let mut config: Config = serde_yaml::from_str(&format!(
+            r"
+catalogues:
+    fan: {}
+groups:
+    1:
+        locale: en
+    2:
+        locale: fan
+    3:
+        overrides:
+            core.pong: 啪
+            core.role_query: '{{role}} {{who}}'
+",
+            path.display()
+        ))?;
+        config.load_catalogues()?;
+
+        assert_eq!(config.for_group(None).get("core.pong"), "pong");
+        assert_eq!(
+            config.for_group(Some(QQ(1))).get("core.permission_denied"),
+            "Permission denied"
+        );
+        assert_eq!(config.for_group(Some(QQ(2))).get("core.pong"), "在呢");
+        // 自定义目录缺少的条目使用内置中文
+        assert_eq!(
+            config.for_group(Some(QQ(2))).get("core.permission_denied"),
+            "权限不足"
+        );
+        assert_eq!(config.for_group(Some(QQ(3))).get("core.pong"), "啪");
+        assert_eq!(config.for_group(Some(QQ(4))).get("core.pong"), "pong");
+
+        assert_eq!(
+            config.check(),
+            vec![
+                "messages.groups.3.overrides 中 core.role_query 的占位符 {who} 不存在",
+                "目录 fan 中的 not.a.key 不是已知的键",
+            ]
+        );
+        config.locale = "fr".to_string();
+        assert_eq!(config.check().len(), 3);
+        Ok(())
+    }
+}
@@ -1,13 +1,47 @@
 #[macro_use]
 extern crate log;
 
-mod config;
+pub mod admin;
+pub mod audit;
+pub mod backup;
+pub mod bvid;
+pub mod check;
+pub mod cli;
+pub mod config;
+#[cfg(any(test, feature = "mock"))]
+pub mod console;
+pub mod ext;
+pub mod group_switch;
+pub mod http;
+pub mod i18n;
+pub mod metrics;
+pub mod middleware;
+pub mod migrate;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod permission;
 pub mod plugins;
+pub mod rate_limit;
+pub mod report;
+pub mod router;
+pub mod shutdown;
+pub mod store;
+pub mod supervisor;
+pub mod trace;
+pub mod watcher;
 pub mod prelude {
+    pub use crate::ext::ConversationExt;
     pub use anyhow::*;
     pub use miraie::prelude::*;
     pub use serde::{Deserialize, Serialize};
     pub use std::time::Duration;
     pub use tokio::time::sleep;
 }
-pub use config::Config;
+pub use config::{Config, ConfigSource, SharedConfig};
+pub use group_switch::GroupSwitch;
+pub use http::HttpClient;
+pub use i18n::Text;
+pub use middleware::Middleware;
+pub use permission::{Permissions, Role};
+pub use rate_limit::RateLimiter;
+pub use store::Store;
@@ -1,54 +1,118 @@
-use log::*;
+use clap::Parser;
 
+use avabot::cli::{Args, Command, DbCommand};
 use avabot::prelude::*;
-use avabot::{plugins, Config};
+use avabot::supervisor::Supervisor;
+use avabot::{ConfigSource, Store};
 
-async fn run() -> Result<()> {
-    let config = Config::new()?;
+#[tokio::main]
+async fn main() -> Result<()> {
+    // allow .env not found
+    dotenv::dotenv().ok();
+    let args = Args::parse();
+    let source = ConfigSource {
+        path: args.config,
+        db_path: args.db_path,
+        console: args.console,
+    };
+    match args.command {
+        Some(Command::CheckConfig { path }) => {
+            let source = ConfigSource {
+                path: path.unwrap_or(source.path),
+                ..source
+            };
+            check_config(&source);
+        }
+        Some(Command::ExportAudit { account }) => return export_audit(&source, account),
+        Some(Command::Db { command }) => return db_command(&source, command),
+        None => {}
+    }
+
+    log4rs::init_file(&args.log_config, Default::default()).context("log4rs 初始化失败")?;
 
-    let (mut bot, con) = miraie::Bot::new(&config.addr, &config.verify_key, config.qq).await?;
-    info!("连接已建立。");
+    let supervisors = Supervisor::new(source)?;
+    if args.console {
+        let group = args.console_group.map(QQ);
+        return console(&supervisors[0], QQ(args.console_sender), group).await;
+    }
+    Supervisor::run_all(&supervisors).await;
+
+    Ok(())
+}
+
+/// 控制台模式，见 `avabot::console`
+#[cfg(feature = "mock")]
+async fn console(supervisor: &Supervisor, sender: QQ, group: Option<QQ>) -> Result<()> {
+    let options = avabot::console::Options { sender, group };
+    avabot::console::run(supervisor, options).await
+}
 
-    bot = bot.bot_data(Data::new(config));
+#[cfg(not(feature = "mock"))]
+async fn console(_supervisor: &Supervisor, _sender: QQ, _group: Option<QQ>) -> Result<()> {
+    bail!("控制台模式需要启用 mock feature，请使用 cargo run --features mock -- --console")
+}
 
-    plugins::core::init(bot.clone());
-    plugins::asoul_cnki::init(bot.clone());
-    plugins::keyword_reply::init(bot.clone());
-    plugins::bilibili_cover::init(bot.clone());
-    plugins::asoul_weekly::init(bot.clone());
-    plugins::schedule::init(bot.clone());
-    plugins::shab::init(bot.clone());
-    plugins::fraud::init(bot.clone());
+/// 不运行机器人，直接打开数据库
+fn open_db(source: &ConfigSource) -> Result<sled::Db> {
+    let config = source.load()?;
+    sled::open(&config.db_path).context("打开数据库失败，需要先停止机器人")
+}
 
-    con.run().await?;
+/// 把审计日志输出到标准输出
+fn export_audit(source: &ConfigSource, account: Option<u64>) -> Result<()> {
+    let config = source.load()?;
+    let db = open_db(source)?;
+    let store = if config.accounts.is_empty() {
+        Store::new(db)
+    } else {
+        let account = match account {
+            Some(qq) => config
+                .accounts
+                .iter()
+                .find(|a| a.qq.0 == qq)
+                .with_context(|| format!("没有配置账号 {}", qq))?,
+            None => &config.accounts[0],
+        };
+        Store::for_account(db, account.qq)
+    };
+    let entries = avabot::audit::AuditLog::new(&store)?.all()?;
+    println!("{}", serde_json::to_string_pretty(&entries)?);
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // allow .env not found
-    dotenv::dotenv().ok();
-    log4rs::init_file("log4rs.yml", Default::default()).context("log4rs 初始化失败")?;
-
-    // try boot
-    let mut counter = 0;
-    loop {
-        counter += 1;
-        match run().await {
-            Err(e) => {
-                if counter > 10 {
-                    error!("尝试重启次数过多，停止");
-                    break;
-                }
-                warn!("启动失败：{:?}", e);
-                info!("等待 {} s.", counter);
-                sleep(Duration::from_secs(counter)).await;
-            }
-            Ok(_) => {
-                break;
+/// 导出或导入整个数据库
+fn db_command(source: &ConfigSource, command: DbCommand) -> Result<()> {
+    let db = open_db(source)?;
+    match command {
+        DbCommand::Export { output } => {
+            let json = serde_json::to_string_pretty(&avabot::backup::export(&db)?)?;
+            match output {
+                Some(path) => std::fs::write(path, json)?,
+                None => println!("{}", json),
             }
         }
+        DbCommand::Import { input } => {
+            let reader = std::fs::File::open(&input)
+                .with_context(|| format!("打开 {} 失败", input.display()))?;
+            let dump = serde_json::from_reader(reader)?;
+            let count = avabot::backup::import(&db, &dump)?;
+            eprintln!("已导入 {} 条记录", count);
+        }
     }
-
     Ok(())
 }
+
+/// 检查配置文件后直接退出
+fn check_config(source: &ConfigSource) -> ! {
+    let problems = avabot::check::check_config(source);
+    let path = source.path.display();
+    if problems.is_empty() {
+        println!("{} 检查通过", path);
+        std::process::exit(0);
+    }
+    for problem in problems.iter() {
+        eprintln!("{}", problem);
+    }
+    eprintln!("{} 发现 {} 个问题", path, problems.len());
+    std::process::exit(1);
+}
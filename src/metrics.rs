@@ -0,0 +1,282 @@
+//! 监控
+//!
+//! 配置 `metrics.listen` 后启动一个 HTTP 服务：`/healthz` 返回与 mirai 的连接状态和距离上一个
+//! 事件的时间，`/metrics` 返回 Prometheus 格式的指标。指标是全局的，不开启 HTTP 服务时也会统计。
+
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, StatusCode};
+use parking_lot::Mutex;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+    Opts, Registry, TextEncoder,
+};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 监听地址，如 `0.0.0.0:9090`，不填时不启动 HTTP 服务
+    #[serde(default)]
+    pub listen: Option<String>,
+}
+
+pub struct Metrics {
+    registry: Registry,
+    /// 按插件统计的命令次数
+    commands: IntCounterVec,
+    /// 按插件统计的处理耗时
+    handler_seconds: HistogramVec,
+    /// 按插件统计的处理失败次数，`kind` 为 `user` 或 `internal`
+    errors: IntCounterVec,
+    /// 对外请求的返回状态，`status` 为 HTTP 状态码，请求没有发出时为 `error`；
+    /// 通过 biliapi 的请求拿不到状态码，只区分 `ok` 和 `error`
+    http_responses: IntCounterVec,
+    /// 各账号是否已经连上 mirai
+    connected: IntGaugeVec,
+    /// 同 `connected`，用于 `/healthz`
+    accounts: Mutex<BTreeMap<u64, bool>>,
+    /// 上一个事件的 unix 时间戳，没有收到过事件时为 0
+    last_event: IntGauge,
+}
+
+lazy_static::lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::new().expect("注册指标失败");
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let commands = IntCounterVec::new(
+            Opts::new("avabot_commands_total", "插件处理的命令数"),
+            &["plugin"],
+        )?;
+        let handler_seconds = HistogramVec::new(
+            HistogramOpts::new("avabot_handler_duration_seconds", "插件处理命令的耗时"),
+            &["plugin"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new("avabot_handler_errors_total", "插件处理命令失败的次数"),
+            &["plugin", "kind"],
+        )?;
+        let http_responses = IntCounterVec::new(
+            Opts::new("avabot_http_responses_total", "对外 HTTP 请求的返回状态"),
+            &["service", "status"],
+        )?;
+        let connected = IntGaugeVec::new(
+            Opts::new("avabot_connected", "账号是否已经连上 mirai"),
+            &["account"],
+        )?;
+        let last_event = IntGauge::new(
+            "avabot_last_event_timestamp_seconds",
+            "上一个事件的 unix 时间戳",
+        )?;
+        registry.register(Box::new(commands.clone()))?;
+        registry.register(Box::new(handler_seconds.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(http_responses.clone()))?;
+        registry.register(Box::new(connected.clone()))?;
+        registry.register(Box::new(last_event.clone()))?;
+        Ok(Self {
+            registry,
+            commands,
+            handler_seconds,
+            errors,
+            http_responses,
+            connected,
+            accounts: Mutex::new(BTreeMap::new()),
+            last_event,
+        })
+    }
+
+    /// 开始处理插件 `plugin` 的命令，返回的计时器被 drop 时记录耗时
+    pub fn start_command(&self, plugin: &str) -> HistogramTimer {
+        self.commands.with_label_values(&[plugin]).inc();
+        self.handler_seconds
+            .with_label_values(&[plugin])
+            .start_timer()
+    }
+
+    pub fn command_failed(&self, plugin: &str, e: &Error) {
+        let kind = match e.downcast_ref::<crate::report::UserError>() {
+            Some(_) => "user",
+            None => "internal",
+        };
+        self.errors.with_label_values(&[plugin, kind]).inc();
+    }
+
+    pub fn set_connected(&self, account: QQ, connected: bool) {
+        let label = account.0.to_string();
+        self.connected
+            .with_label_values(&[label.as_str()])
+            .set(connected as i64);
+        self.accounts.lock().insert(account.0, connected);
+    }
+
+    pub fn event_received(&self) {
+        self.last_event.set(chrono::Utc::now().timestamp());
+    }
+
+    /// 记录对外请求的返回状态
+    pub fn http_status(&self, service: &str, status: &str) {
+        self.http_responses
+            .with_label_values(&[service, status])
+            .inc();
+    }
+
+    /// 记录 reqwest 请求的结果
+    pub fn http_response(&self, service: &str, response: &reqwest::Result<reqwest::Response>) {
+        match response {
+            Ok(response) => self.http_status(service, response.status().as_str()),
+            Err(e) => match e.status() {
+                Some(status) => self.http_status(service, status.as_str()),
+                None => self.http_status(service, "error"),
+            },
+        }
+    }
+
+    /// 记录拿不到状态码的请求（如 biliapi）的结果
+    pub fn api_result<T, E>(&self, service: &str, result: &Result<T, E>) {
+        let status = if result.is_ok() { "ok" } else { "error" };
+        self.http_status(service, status);
+    }
+
+    /// `/healthz` 的返回，有账号未连接时为 503
+    fn health(&self, now: i64) -> (StatusCode, serde_json::Value) {
+        let accounts = self.accounts.lock().clone();
+        let connected = !accounts.is_empty() && accounts.values().all(|c| *c);
+        let last_event = self.last_event.get();
+        let since_last_event = (last_event > 0).then(|| now - last_event);
+        let status = if connected {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        let body = serde_json::json!({
+            "connected": connected,
+            "accounts": accounts
+                .iter()
+                .map(|(qq, connected)| (qq.to_string(), *connected))
+                .collect::<BTreeMap<_, _>>(),
+            "seconds_since_last_event": since_last_event,
+        });
+        (status, body)
+    }
+
+    fn render(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// 注册记录事件时间的 handler
+pub fn init(bot: Bot) {
+    bot.handler(on_event::<GroupMessage>)
+        .handler(on_event::<FriendMessage>);
+}
+
+async fn on_event<T>(_event: T) -> Result<()> {
+    METRICS.event_received();
+    Ok(())
+}
+
+async fn handle(metrics: &Metrics, req: Request<Body>) -> Response<Body> {
+    let response = Response::builder();
+    match req.uri().path() {
+        "/healthz" => {
+            let (status, body) = metrics.health(chrono::Utc::now().timestamp());
+            response
+                .status(status)
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+        }
+        "/metrics" => match metrics.render() {
+            Ok(buf) => response
+                .header("Content-Type", TextEncoder::new().format_type())
+                .body(Body::from(buf)),
+            Err(e) => {
+                error!("生成指标失败：{:?}", e);
+                response
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+            }
+        },
+        _ => response.status(StatusCode::NOT_FOUND).body(Body::empty()),
+    }
+    .expect("构造 HTTP 响应失败")
+}
+
+/// 在 `listen` 上启动 HTTP 服务，一直运行
+pub async fn serve(listen: &str) -> Result<()> {
+    let addr: SocketAddr = listen
+        .parse()
+        .with_context(|| format!("监听地址 {} 不合法", listen))?;
+    let make_service = make_service_fn(|_| async {
+        Result::<_, Infallible>::Ok(service_fn(|req| async move {
+            Result::<_, Infallible>::Ok(handle(&METRICS, req).await)
+        }))
+    });
+    let server = hyper::Server::try_bind(&addr)
+        .with_context(|| format!("监听 {} 失败", addr))?
+        .serve(make_service);
+    info!("监控服务监听 {}", server.local_addr());
+    server.await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(metrics: &Metrics, path: &str) -> (StatusCode, String) {
+        let req = Request::get(path).body(Body::empty()).unwrap();
+        let response = handle(metrics, req).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() -> Result<()> {
+        let metrics = Metrics::new()?;
+        let (status, body) = get(&metrics, "/healthz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(body["connected"], false);
+        assert!(body["seconds_since_last_event"].is_null());
+
+        metrics.set_connected(QQ(1), true);
+        metrics.set_connected(QQ(2), false);
+        assert_eq!(metrics.health(0).0, StatusCode::SERVICE_UNAVAILABLE);
+        metrics.set_connected(QQ(2), true);
+        metrics.last_event.set(100);
+        let (status, body) = metrics.health(130);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["accounts"]["2"], true);
+        assert_eq!(body["seconds_since_last_event"], 30);
+
+        drop(metrics.start_command("ping"));
+        metrics.command_failed("fraud", &anyhow!("x"));
+        metrics.command_failed(
+            "fraud",
+            &crate::report::UserError::new("fraud.no_video").into(),
+        );
+        metrics.http_status("cnki", "200");
+        let (status, body) = get(&metrics, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"avabot_commands_total{plugin="ping"} 1"#));
+        assert!(body.contains(r#"avabot_handler_duration_seconds_count{plugin="ping"} 1"#));
+        assert!(body.contains(r#"avabot_handler_errors_total{kind="internal",plugin="fraud"} 1"#));
+        assert!(body.contains(r#"avabot_handler_errors_total{kind="user",plugin="fraud"} 1"#));
+        assert!(body.contains(r#"avabot_http_responses_total{service="cnki",status="200"} 1"#));
+        assert!(body.contains(r#"avabot_connected{account="1"} 1"#));
+
+        assert_eq!(get(&metrics, "/").await.0, StatusCode::NOT_FOUND);
+        Ok(())
+    }
+}
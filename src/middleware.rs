@@ -0,0 +1,117 @@
+//! handler 的公共处理：冷却、错误上报、审计、监控指标、日志追踪与优雅退出
+//!
+//! 插件在通过权限检查后调用 [`Middleware::admit`]，再用 [`Middleware::run`] 运行实际的处理逻辑。
+//! 处理逻辑返回的错误不会再交给 miraie，而是回复用户并通知管理员。两者期间的日志都带有 [`Span`] 的字段。
+
+use std::future::Future;
+
+use crate::audit::AuditLog;
+use crate::metrics::METRICS;
+use crate::prelude::*;
+use crate::rate_limit::RateLimiter;
+use crate::report::{user_message, ErrorContext, ErrorReporter};
+use crate::shutdown::Shutdown;
+use crate::trace::Span;
+use crate::{Permissions, SharedConfig, Store};
+
+#[derive(Clone)]
+pub struct Middleware {
+    config: SharedConfig,
+    limiter: RateLimiter,
+    permissions: Permissions,
+    reporter: ErrorReporter,
+    audit: AuditLog,
+    shutdown: Shutdown,
+}
+
+impl Middleware {
+    pub fn new(
+        store: &Store,
+        config: SharedConfig,
+        permissions: Permissions,
+        shutdown: Shutdown,
+    ) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            limiter: RateLimiter::new(config.clone()),
+            permissions,
+            reporter: ErrorReporter::new(config),
+            audit: AuditLog::new(store)?,
+            shutdown,
+        })
+    }
+
+    pub fn reporter(&self) -> &ErrorReporter {
+        &self.reporter
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// 按发送者的角色检查冷却，见 [`RateLimiter::admit`]；正在退出时不再处理新的命令
+    pub async fn admit<T: Conversation + ConversationExt + Sync>(
+        &self,
+        msg: &T,
+        plugin: &'static str,
+        bot: &Bot,
+    ) -> Result<bool> {
+        if self.shutdown.is_stopping() {
+            return Ok(false);
+        }
+        Span::of(msg, plugin)
+            .instrument(
+                self.limiter
+                    .admit(msg, plugin, self.permissions.role(msg), bot),
+            )
+            .await
+    }
+
+    /// 运行插件 `plugin` 对消息 `msg` 的处理，出错时回复用户、通知管理员，并返回默认值
+    pub async fn run<T, R, F>(&self, msg: &T, bot: &Bot, plugin: &'static str, handler: F) -> R
+    where
+        T: Conversation + ConversationExt + Sync,
+        R: Default,
+        F: Future<Output = Result<R>>,
+    {
+        let span = Span::of(msg, plugin);
+        span.instrument(self.run_in(&span, msg, bot, handler)).await
+    }
+
+    async fn run_in<T, R, F>(&self, span: &Span, msg: &T, bot: &Bot, handler: F) -> R
+    where
+        T: Conversation + ConversationExt + Sync,
+        R: Default,
+        F: Future<Output = Result<R>>,
+    {
+        let plugin = span.plugin();
+        let _guard = match self.shutdown.enter() {
+            Some(guard) => guard,
+            None => {
+                debug!("正在退出，忽略插件 {} 的命令", plugin);
+                return R::default();
+            }
+        };
+        let timer = METRICS.start_command(plugin);
+        let result = handler.await;
+        timer.observe_duration();
+        match result {
+            Ok(r) => r,
+            Err(e) => {
+                METRICS.command_failed(plugin, &e);
+                let reply =
+                    user_message(&e, &self.config.load().messages.for_group(msg.group_id()));
+                if let Err(reply_error) = msg.reply(reply, bot).await {
+                    warn!("回复错误提示失败：{:?}", reply_error);
+                }
+                let ctx = ErrorContext::of(msg, span);
+                self.reporter.report(bot, &ctx, &e).await;
+                R::default()
+            }
+        }
+    }
+}
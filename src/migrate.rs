@@ -0,0 +1,186 @@
+//! 数据库迁移
+//!
+//! 每棵树的版本号记录在 `__schema` 树中，没有记录时为 0。启动时对每个账号的 [`Store`] 按顺序执行
+//! [`MIGRATIONS`] 中版本号高于当前版本的迁移，每执行一个就更新一次版本号，中途失败时下次启动从失败处继续。
+//! 版本号按带账号前缀的完整树名记录，多账号时各个账号分别迁移。
+//! 新的迁移追加在列表末尾，同一棵树的版本号从 1 开始连续递增。
+
+use crate::prelude::*;
+use crate::Store;
+
+pub(crate) const SCHEMA_TREE: &str = "__schema";
+
+pub struct Migration {
+    /// 迁移的树，不带账号前缀
+    pub tree: &'static str,
+    /// 迁移完成后的版本
+    pub version: u32,
+    pub description: &'static str,
+    pub run: fn(&Store) -> Result<()>,
+}
+
+/// 全部迁移，按执行顺序排列
+pub static MIGRATIONS: &[Migration] = &[Migration {
+    tree: "plugin/schedule",
+    version: 1,
+    description: "日程表链接从默认树搬到 schedule 命名空间",
+    run: crate::plugins::schedule::migrate_legacy_url,
+}];
+
+/// `store` 中树 `tree` 当前的版本
+pub fn version(store: &Store, tree: &str) -> Result<u32> {
+    let schema = store.db().open_tree(SCHEMA_TREE)?;
+    match schema.get(store.tree_name(tree))? {
+        Some(v) => {
+            let bytes: [u8; 4] = v.as_ref().try_into().context("版本号格式错误")?;
+            Ok(u32::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
+fn set_version(store: &Store, tree: &str, version: u32) -> Result<()> {
+    let schema = store.db().open_tree(SCHEMA_TREE)?;
+    schema.insert(store.tree_name(tree), &version.to_be_bytes()[..])?;
+    schema.flush()?;
+    Ok(())
+}
+
+/// 对 `store` 执行还没有执行过的迁移，返回执行了的迁移的说明
+pub fn run(store: &Store, migrations: &[Migration]) -> Result<Vec<String>> {
+    let mut done = vec![];
+    for migration in migrations {
+        let tree = store.tree_name(migration.tree);
+        let current = version(store, migration.tree)?;
+        if migration.version <= current {
+            continue;
+        }
+        if migration.version != current + 1 {
+            bail!(
+                "{} 当前版本为 {}，无法直接迁移到 {}",
+                tree,
+                current,
+                migration.version
+            );
+        }
+        info!(
+            "迁移 {} 到版本 {}：{}",
+            tree, migration.version, migration.description
+        );
+        (migration.run)(store)
+            .with_context(|| format!("迁移 {} 到版本 {} 失败", tree, migration.version))?;
+        set_version(store, migration.tree, migration.version)?;
+        done.push(format!(
+            "{} v{}：{}",
+            tree, migration.version, migration.description
+        ));
+    }
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_one(store: &Store) -> Result<()> {
+        let tree = store.tree("test")?;
+        let n = tree.get("n")?.map(|v| v.as_ref()[0]).unwrap_or_default();
+        tree.insert("n", vec![n + 1])?;
+        Ok(())
+    }
+
+    fn fail(_: &Store) -> Result<()> {
+        bail!("failed")
+    }
+
+    fn migration(version: u32, run: fn(&Store) -> Result<()>) -> Migration {
+        Migration {
+            tree: "test",
+            version,
+            description: "test",
+            run,
+        }
+    }
+
+    fn counter(store: &Store) -> Result<u8> {
+        Ok(store
+            .tree("test")?
+            .get("n")?
+            .map(|v| v[0])
+            .unwrap_or_default())
+    }
+
+    #[test]
+    fn test_run() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = Store::new(sled::open(dir.path())?);
+        assert_eq!(version(&store, "test")?, 0);
+
+        let migrations = [migration(1, add_one), migration(2, add_one)];
+        assert_eq!(run(&store, &migrations)?.len(), 2);
+        assert_eq!(version(&store, "test")?, 2);
+        assert_eq!(counter(&store)?, 2);
+
+        // 已经执行过的迁移不会再执行
+        assert!(run(&store, &migrations)?.is_empty());
+        assert_eq!(counter(&store)?, 2);
+
+        // 失败时停在失败前的版本
+        let migrations = [
+            migration(1, add_one),
+            migration(2, add_one),
+            migration(3, add_one),
+            migration(4, fail),
+            migration(5, add_one),
+        ];
+        assert!(run(&store, &migrations).is_err());
+        assert_eq!(version(&store, "test")?, 3);
+        assert_eq!(counter(&store)?, 3);
+
+        // 版本号不连续
+        assert!(run(&store, &[migration(5, add_one)]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_prefix() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = sled::open(dir.path())?;
+        let legacy = Store::new(db.clone());
+        let first = Store::for_account(db.clone(), QQ(1));
+        let second = Store::for_account(db.clone(), QQ(2));
+        let migrations = [migration(1, add_one), migration(2, add_one)];
+
+        assert_eq!(
+            run(&first, &migrations)?,
+            vec!["account/1/test v1：test", "account/1/test v2：test",]
+        );
+        assert_eq!(counter(&first)?, 2);
+        assert_eq!(db.open_tree("account/1/test")?.len(), 1);
+        // 版本号按带前缀的树名记录，其他账号和不带前缀的树不受影响
+        let schema = db.open_tree(SCHEMA_TREE)?;
+        assert!(schema.get("account/1/test")?.is_some());
+        assert_eq!(version(&second, "test")?, 0);
+        assert_eq!(version(&legacy, "test")?, 0);
+        assert_eq!(counter(&legacy)?, 0);
+
+        assert_eq!(run(&second, &migrations)?.len(), 2);
+        assert_eq!(counter(&second)?, 2);
+        assert!(run(&first, &migrations)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_builtin_migrations() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = sled::open(dir.path())?;
+        for store in [Store::new(db.clone()), Store::for_account(db, QQ(1))] {
+            run(&store, MIGRATIONS)?;
+            for migration in MIGRATIONS {
+                assert!(version(&store, migration.tree)? >= migration.version);
+            }
+            assert!(run(&store, MIGRATIONS)?.is_empty());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+//! 模拟 mirai-api-http 的 WebSocket 接口
+//!
+//! 用于在没有 mirai 的情况下驱动插件：向机器人推送消息事件，并记录机器人调用的接口，
+//! 例如发送、撤回消息。协议见 mirai-api-http v2 的 WebSocket adapter，只实现了插件用到的部分。
+
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+
+/// 等待机器人调用接口的默认超时
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 机器人对 mirai 的一次调用
+#[derive(Debug, Clone)]
+pub struct Call {
+    pub command: String,
+    pub sub_command: Option<String>,
+    pub content: Value,
+}
+
+impl Call {
+    /// 发送目标，群号或 QQ 号
+    pub fn target(&self) -> Option<u64> {
+        self.content.get("target").and_then(Value::as_u64)
+    }
+
+    pub fn message_chain(&self) -> &[Value] {
+        self.content
+            .get("messageChain")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// 消息链中的全部文本
+    pub fn text(&self) -> String {
+        self.message_chain()
+            .iter()
+            .filter(|b| b["type"] == "Plain")
+            .filter_map(|b| b["text"].as_str())
+            .collect()
+    }
+}
+
+/// 纯文本消息块
+pub fn plain(text: &str) -> Value {
+    json!({ "type": "Plain", "text": text })
+}
+
+/// @ 消息块
+pub fn at(target: u64) -> Value {
+    json!({ "type": "At", "target": target, "display": format!("@{}", target) })
+}
+
+/// 引用回复消息块
+pub fn quote(id: i64, group: u64, sender: u64, origin: Vec<Value>) -> Value {
+    json!({
+        "type": "Quote",
+        "id": id,
+        "groupId": group,
+        "senderId": sender,
+        "targetId": group,
+        "origin": origin,
+    })
+}
+
+struct Shared {
+    events: broadcast::Sender<String>,
+    calls: mpsc::UnboundedSender<Call>,
+    connections: AtomicUsize,
+    connections_tx: watch::Sender<usize>,
+    next_message_id: AtomicI64,
+}
+
+impl Shared {
+    fn next_message_id(&self) -> i64 {
+        self.next_message_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// 处理一条命令，返回响应
+    fn handle_command(&self, text: &str) -> Result<String> {
+        let request: Value = serde_json::from_str(text).context("命令不是合法的 json")?;
+        let sync_id = request["syncId"].clone();
+        let command = request["command"]
+            .as_str()
+            .context("命令缺少 command")?
+            .to_string();
+        let data = match command.as_str() {
+            "sendFriendMessage" | "sendGroupMessage" | "sendTempMessage" => {
+                json!({ "code": 0, "msg": "success", "messageId": self.next_message_id() })
+            }
+            "about" => json!({ "code": 0, "msg": "", "data": { "version": "2.4.0" } }),
+            _ => json!({ "code": 0, "msg": "success" }),
+        };
+        let call = Call {
+            command,
+            sub_command: request["subCommand"].as_str().map(str::to_string),
+            content: request["content"].clone(),
+        };
+        debug!("mock mirai 收到调用：{:?}", call);
+        self.calls.send(call).ok();
+        Ok(json!({ "syncId": sync_id, "data": data }).to_string())
+    }
+}
+
+pub struct MockMirai {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    calls: Mutex<mpsc::UnboundedReceiver<Call>>,
+    connections: watch::Receiver<usize>,
+}
+
+impl MockMirai {
+    /// 在随机端口上启动
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (events, _) = broadcast::channel(64);
+        let (calls_tx, calls) = mpsc::unbounded_channel();
+        let (connections_tx, connections) = watch::channel(0);
+        let shared = Arc::new(Shared {
+            events,
+            calls: calls_tx,
+            connections: AtomicUsize::new(0),
+            connections_tx,
+            next_message_id: AtomicI64::new(1),
+        });
+
+        let server = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("mock mirai accept 失败：{:?}", e);
+                        continue;
+                    }
+                };
+                let shared = server.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, shared).await {
+                        warn!("mock mirai 连接出错：{:?}", e);
+                    }
+                });
+            }
+        });
+        info!("mock mirai 监听 {}", addr);
+
+        Ok(Self {
+            addr,
+            shared,
+            calls: Mutex::new(calls),
+            connections,
+        })
+    }
+
+    /// 形如 `127.0.0.1:12345`，即配置中的 `addr`
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// 等待机器人连接上来，之后推送的事件才能收到
+    pub async fn wait_connected(&self) -> Result<()> {
+        let mut connections = self.connections.clone();
+        while *connections.borrow() == 0 {
+            connections.changed().await?;
+        }
+        Ok(())
+    }
+
+    /// 推送一个事件，`data` 即 mirai-api-http 中的事件
+    pub fn push_event(&self, data: Value) {
+        let event = json!({ "syncId": "-1", "data": data }).to_string();
+        if self.shared.events.send(event).is_err() {
+            warn!("mock mirai 没有连接，事件被丢弃");
+        }
+    }
+
+    /// 推送一条群消息，`permission` 为 `OWNER`、`ADMINISTRATOR` 或 `MEMBER`，返回消息 id
+    pub fn group_message_with_permission(
+        &self,
+        group: u64,
+        sender: u64,
+        permission: &str,
+        chain: Vec<Value>,
+    ) -> i64 {
+        let id = self.shared.next_message_id();
+        let mut message_chain = vec![json!({ "type": "Source", "id": id, "time": now() })];
+        message_chain.extend(chain);
+        self.push_event(json!({
+            "type": "GroupMessage",
+            "messageChain": message_chain,
+            "sender": {
+                "id": sender,
+                "memberName": format!("用户{}", sender),
+                "specialTitle": "",
+                "permission": permission,
+                "joinTimestamp": 0,
+                "lastSpeakTimestamp": 0,
+                "muteTimeRemaining": 0,
+                "group": {
+                    "id": group,
+                    "name": format!("群{}", group),
+                    "permission": "MEMBER",
+                },
+            },
+        }));
+        id
+    }
+
+    /// 推送一条普通群员发的群消息，返回消息 id
+    pub fn group_message(&self, group: u64, sender: u64, chain: Vec<Value>) -> i64 {
+        self.group_message_with_permission(group, sender, "MEMBER", chain)
+    }
+
+    /// 推送一条纯文本群消息，返回消息 id
+    pub fn group_text(&self, group: u64, sender: u64, text: &str) -> i64 {
+        self.group_message(group, sender, vec![plain(text)])
+    }
+
+    /// 推送一条好友消息，返回消息 id
+    pub fn friend_message(&self, sender: u64, chain: Vec<Value>) -> i64 {
+        let id = self.shared.next_message_id();
+        let mut message_chain = vec![json!({ "type": "Source", "id": id, "time": now() })];
+        message_chain.extend(chain);
+        self.push_event(json!({
+            "type": "FriendMessage",
+            "messageChain": message_chain,
+            "sender": {
+                "id": sender,
+                "nickname": format!("用户{}", sender),
+                "remark": "",
+            },
+        }));
+        id
+    }
+
+    /// 推送一条纯文本好友消息，返回消息 id
+    pub fn friend_text(&self, sender: u64, text: &str) -> i64 {
+        self.friend_message(sender, vec![plain(text)])
+    }
+
+    /// 等待机器人的下一次调用，超时返回 `None`
+    pub async fn next_call(&self, timeout: Duration) -> Option<Call> {
+        let mut calls = self.calls.lock().await;
+        tokio::time::timeout(timeout, calls.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// 等待机器人调用 `command`，跳过其他调用
+    pub async fn expect_call(&self, command: &str) -> Result<Call> {
+        let deadline = tokio::time::Instant::now() + DEFAULT_TIMEOUT;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match self.next_call(remaining).await {
+                Some(call) if call.command == command => return Ok(call),
+                Some(call) => debug!("跳过调用 {}", call.command),
+                None => anyhow::bail!("等待 {} 超时", command),
+            }
+        }
+    }
+
+    /// 在 `timeout` 内机器人没有任何调用
+    pub async fn expect_silence(&self, timeout: Duration) -> Result<()> {
+        match self.next_call(timeout).await {
+            Some(call) => anyhow::bail!("预期没有调用，实际收到 {:?}", call),
+            None => Ok(()),
+        }
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+async fn serve(stream: TcpStream, shared: Arc<Shared>) -> Result<()> {
+    let ws = tokio_tungstenite::accept_async(stream).await?;
+    let (mut write, mut read) = ws.split();
+    let mut events = shared.events.subscribe();
+
+    let session = json!({ "syncId": "", "data": { "code": 0, "session": "mock-session" } });
+    write.send(Message::Text(session.to_string())).await?;
+
+    let count = shared.connections.fetch_add(1, Ordering::SeqCst) + 1;
+    shared.connections_tx.send(count).ok();
+
+    let result = loop {
+        tokio::select! {
+            message = read.next() => match message {
+                Some(Ok(Message::Text(text))) => match shared.handle_command(&text) {
+                    Ok(response) => write.send(Message::Text(response)).await?,
+                    Err(e) => warn!("mock mirai 无法处理命令 {}：{:?}", text, e),
+                },
+                Some(Ok(Message::Close(_))) | None => break Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => break Err(e.into()),
+            },
+            event = events.recv() => match event {
+                Ok(event) => write.send(Message::Text(event)).await?,
+                Err(broadcast::error::RecvError::Lagged(n)) => warn!("mock mirai 丢弃了 {} 个事件", n),
+                Err(broadcast::error::RecvError::Closed) => break Ok(()),
+            },
+        }
+    };
+
+    let count = shared.connections.fetch_sub(1, Ordering::SeqCst) - 1;
+    shared.connections_tx.send(count).ok();
+    result
+}
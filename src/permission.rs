@@ -0,0 +1,250 @@
+//! 权限
+//!
+//! 超级管理员即 `config.yaml` 中的 `admins`，其余角色按群授予，保存在 sled 的 `permission`
+//! tree 中，键为 `群号/QQ`。插件的按群开关也在这里检查。
+
+use anyhow::{bail, Result};
+use miraie::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::ext::ConversationExt;
+use crate::{GroupSwitch, SharedConfig, Store, Text};
+
+static TREE: &str = "permission";
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 将 QQ 群主和管理员视为群管理
+    #[serde(default)]
+    pub honour_group_permission: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// 被封禁，不响应任何消息
+    Banned,
+    /// 普通用户
+    Member,
+    /// 信任用户
+    Trusted,
+    /// 群管理
+    GroupAdmin,
+    /// 超级管理员
+    SuperAdmin,
+}
+
+impl Role {
+    /// 数据库中保存的名字
+    fn key(&self) -> &'static str {
+        match self {
+            Role::Banned => "banned",
+            Role::Member => "member",
+            Role::Trusted => "trusted",
+            Role::GroupAdmin => "group_admin",
+            Role::SuperAdmin => "super_admin",
+        }
+    }
+
+    fn from_key(s: &str) -> Option<Self> {
+        [
+            Role::Banned,
+            Role::Member,
+            Role::Trusted,
+            Role::GroupAdmin,
+            Role::SuperAdmin,
+        ]
+        .into_iter()
+        .find(|r| r.key() == s)
+    }
+
+    /// 显示给用户的名字
+    pub fn text(&self) -> Text {
+        Text::new(match self {
+            Role::Banned => "role.banned",
+            Role::Member => "role.member",
+            Role::Trusted => "role.trusted",
+            Role::GroupAdmin => "role.group_admin",
+            Role::SuperAdmin => "role.super_admin",
+        })
+    }
+
+    /// 从指令中的中文名解析，超级管理员只能在配置文件中设置
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "封禁" => Some(Role::Banned),
+            "普通" => Some(Role::Member),
+            "信任" => Some(Role::Trusted),
+            "群管理" => Some(Role::GroupAdmin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Permissions {
+    tree: sled::Tree,
+    switch: GroupSwitch,
+    config: SharedConfig,
+    /// 机器人自己的 QQ 号
+    account: QQ,
+}
+
+impl Permissions {
+    pub fn new(store: &Store, config: SharedConfig, account: QQ) -> Result<Self> {
+        Ok(Self {
+            tree: store.tree(TREE)?,
+            switch: GroupSwitch::new(store)?,
+            config,
+            account,
+        })
+    }
+
+    pub fn switch(&self) -> &GroupSwitch {
+        &self.switch
+    }
+
+    pub fn config(&self) -> &SharedConfig {
+        &self.config
+    }
+
+    pub fn account(&self) -> QQ {
+        self.account
+    }
+
+    fn key(group: QQ, qq: QQ) -> String {
+        format!("{}/{}", group.0, qq.0)
+    }
+
+    /// 群内单独授予的角色
+    pub fn granted(&self, group: QQ, qq: QQ) -> Result<Option<Role>> {
+        let role = self
+            .tree
+            .get(Self::key(group, qq))?
+            .and_then(|v| Role::from_key(&String::from_utf8_lossy(&v)));
+        Ok(role)
+    }
+
+    /// 在群内授予角色，授予普通即移除记录
+    pub fn grant(&self, group: QQ, qq: QQ, role: Role) -> Result<()> {
+        let key = Self::key(group, qq);
+        match role {
+            Role::Member => {
+                self.tree.remove(key)?;
+            }
+            Role::SuperAdmin => bail!("超级管理员只能在配置文件中设置"),
+            role => {
+                self.tree.insert(key, role.key())?;
+            }
+        }
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// 用户在群内的角色，私聊时 `group` 为 `None`
+    pub fn role_in(&self, group: Option<QQ>, qq: QQ, is_group_manager: bool) -> Role {
+        let config = self.config.load();
+        if config.is_admin(qq) {
+            return Role::SuperAdmin;
+        }
+        let group = match group {
+            Some(group) => group,
+            None => return Role::Member,
+        };
+        let granted = match self.granted(group, qq) {
+            Ok(role) => role.unwrap_or(Role::Member),
+            Err(e) => {
+                error!("读取群 {} 用户 {} 权限失败：{:?}", group.0, qq.0, e);
+                Role::Member
+            }
+        };
+        if granted == Role::Banned {
+            return Role::Banned;
+        }
+        if is_group_manager && config.permission.honour_group_permission {
+            return granted.max(Role::GroupAdmin);
+        }
+        granted
+    }
+
+    /// 消息发送者的角色
+    pub fn role<T: Conversation + ConversationExt>(&self, msg: &T) -> Role {
+        self.role_in(
+            msg.group_id(),
+            *msg.sender().as_ref(),
+            msg.is_group_manager(),
+        )
+    }
+
+    /// 本账号响应这个群，插件在本群启用，且发送者的角色满足要求
+    pub fn allows<T: Conversation + ConversationExt>(
+        &self,
+        msg: &T,
+        plugin: &str,
+        required: Role,
+    ) -> bool {
+        if let Some(group) = msg.group_id() {
+            if !self.config.load().group_allowed(self.account, group) {
+                return false;
+            }
+        }
+        if !self.switch.allows(msg, plugin) {
+            return false;
+        }
+        let role = self.role(msg);
+        if role < required {
+            debug!("用户角色 {:?} 低于 {:?}，忽略", role, required);
+            return false;
+        }
+        role > Role::Banned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(honour_group_permission: bool) -> SharedConfig {
+        let mut config = crate::Config::for_test();
+        config.admins.insert(QQ(100));
+        config.permission.honour_group_permission = honour_group_permission;
+        SharedConfig::new(config)
+    }
+
+    #[test]
+    fn test_roles() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = sled::open(dir.path())?;
+        let perms = Permissions::new(&Store::new(db), config(false), QQ(1))?;
+        let group = Some(QQ(1));
+
+        assert_eq!(perms.role_in(group, QQ(100), false), Role::SuperAdmin);
+        assert_eq!(perms.role_in(None, QQ(100), false), Role::SuperAdmin);
+        assert_eq!(perms.role_in(group, QQ(200), false), Role::Member);
+        assert_eq!(perms.role_in(group, QQ(200), true), Role::Member);
+
+        perms.grant(QQ(1), QQ(200), Role::Trusted)?;
+        assert_eq!(perms.role_in(group, QQ(200), false), Role::Trusted);
+        assert_eq!(perms.role_in(Some(QQ(2)), QQ(200), false), Role::Member);
+
+        perms.grant(QQ(1), QQ(200), Role::Member)?;
+        assert_eq!(perms.granted(QQ(1), QQ(200))?, None);
+
+        assert!(perms.grant(QQ(1), QQ(200), Role::SuperAdmin).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_group_permission() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db = sled::open(dir.path())?;
+        let perms = Permissions::new(&Store::new(db), config(true), QQ(1))?;
+        let group = Some(QQ(1));
+
+        assert_eq!(perms.role_in(group, QQ(200), true), Role::GroupAdmin);
+
+        perms.grant(QQ(1), QQ(200), Role::Banned)?;
+        assert_eq!(perms.role_in(group, QQ(200), true), Role::Banned);
+        Ok(())
+    }
+}
@@ -5,26 +5,74 @@ use std::collections::HashMap;
 
 use chrono::{DateTime, Utc};
 
+use super::{CommandInfo, Plugin};
+use crate::i18n::Messages;
+use crate::metrics::METRICS;
 use crate::prelude::*;
+use crate::router;
+use crate::{Config, HttpClient, Middleware, Permissions, Role, Text};
+
+pub struct AsoulCnki;
+
+const CNKI: CommandInfo = CommandInfo {
+    name: "枝网查重",
+    aliases: &[],
+    has_args: false,
+    role: Role::Member,
+    examples: &["[引用消息] 枝网查重", "枝网查重"],
+};
+
+impl Plugin for AsoulCnki {
+    fn name(&self) -> &'static str {
+        "asoul_cnki"
+    }
 
-pub fn init(bot: Bot) {
-    bot.handler(on_message);
-}
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[CNKI]
+    }
 
-async fn on_message(group_message: GroupMessage, bot: Bot) -> Result<()> {
-    let cmd_msg = group_message
-        .message
-        .0
-        .iter()
-        .filter(|b| matches!(b, MessageBlock::Text { .. }))
-        .map(|b| b.to_string())
-        .collect::<Vec<_>>()
-        .join("");
+    fn available(&self, _config: &Config, group: Option<QQ>) -> bool {
+        group.is_some()
+    }
 
-    if cmd_msg.trim() != "枝网查重" {
+    fn init(&self, bot: Bot) {
+        bot.handler(on_message);
+    }
+}
+
+async fn on_message(
+    group_message: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+    http: Data<HttpClient>,
+) -> Result<()> {
+    let config = perms.config().load();
+    if router::route(&config.router, &CNKI, &group_message.message).is_none() {
+        return Ok(());
+    }
+    if !perms.allows(&group_message, AsoulCnki.name(), CNKI.role) {
         return Ok(());
     }
+    if !mw.admit(&group_message, AsoulCnki.name(), &bot).await? {
+        return Ok(());
+    }
+    let messages = config
+        .messages
+        .for_group(Some(group_message.sender.group.id));
+    let handler = cnki(&group_message, &bot, &http, &messages);
+    mw.run(&group_message, &bot, AsoulCnki.name(), handler)
+        .await;
+    Ok(())
+}
 
+/// 查重引用的消息，没有引用时询问查重内容
+async fn cnki(
+    group_message: &GroupMessage,
+    bot: &Bot,
+    http: &HttpClient,
+    messages: &Messages<'_>,
+) -> Result<()> {
     let source = group_message
         .message
         .0
@@ -39,14 +87,16 @@ async fn on_message(group_message: GroupMessage, bot: Bot) -> Result<()> {
         Some(source) => source.clone(),
         None => {
             // 主动要
-            let r = group_message.prompt("输入查重内容", &bot).await?;
+            let r = group_message
+                .prompt(messages.get("cnki.prompt"), bot)
+                .await?;
             r.message
         }
     };
-    let result = get_asoul_cnki(content).await?;
+    let result = get_asoul_cnki(http, messages, content).await?;
 
     // 返回结果
-    group_message.reply(result, &bot).await?;
+    group_message.reply(result, bot).await?;
     Ok(())
 }
 
@@ -93,7 +143,11 @@ struct Reply {
     content: String,
 }
 
-async fn get_asoul_cnki(chain: MessageChain) -> Result<String> {
+async fn get_asoul_cnki(
+    http: &HttpClient,
+    messages: &Messages<'_>,
+    chain: MessageChain,
+) -> Result<String> {
     //
     let s = chain
         .0
@@ -104,11 +158,16 @@ async fn get_asoul_cnki(chain: MessageChain) -> Result<String> {
         })
         .collect::<Vec<_>>()
         .join("");
-    get_asoul_cnki_from_str(&s).await
+    get_asoul_cnki_from_str(http, messages, &s).await
 }
 
-async fn get_asoul_cnki_from_str(s: &str) -> Result<String> {
-    let resp = reqwest::Client::new()
+async fn get_asoul_cnki_from_str(
+    http: &HttpClient,
+    messages: &Messages<'_>,
+    s: &str,
+) -> Result<String> {
+    let resp = http
+        .client()
         .post("https://asoulcnki.asia/v1/api/check")
         .json(&{
             let mut m = HashMap::new();
@@ -116,30 +175,32 @@ async fn get_asoul_cnki_from_str(s: &str) -> Result<String> {
             m
         })
         .send()
-        .await?;
-    let text = resp.text().await?;
+        .await;
+    METRICS.http_response("cnki", &resp);
+    let text = resp?.text().await?;
     debug!("查重返回结果 = {}", text);
     let resp: Response = serde_json::from_str(&text)?;
     if resp.code != 0 || resp.data.is_none() {
         error!("resp.code = {}, message = {}", resp.code, resp.message);
         if resp.message.contains("Illegal Capacity") {
-            return Ok(String::from("小作文太短了捏~"));
+            return Ok(messages.get("cnki.too_short"));
         } else {
             bail!("枝网查重返回错误：{}", resp.message);
         }
     }
 
     let data = resp.data.unwrap();
-    let mut res = format!("查重结果：相似度 {:.2}%", data.similarity * 100.);
+    let mut res = messages
+        .get(Text::new("cnki.result").arg("similarity", format!("{:.2}", data.similarity * 100.)));
     if !data.related.is_empty() {
         let first = &data.related[0];
-        res.push_str(&format!(
-            "\n相似小作文：相似度 {:.2}%\n作者：{}\n链接：{}\n{}",
-            first.similarity * 100.0,
-            first.reply.author,
-            first.reply_url,
-            first.reply.content,
-        ));
+        let related = Text::new("cnki.related")
+            .arg("similarity", format!("{:.2}", first.similarity * 100.0))
+            .arg("author", &first.reply.author)
+            .arg("url", &first.reply_url)
+            .arg("content", &first.reply.content);
+        res.push('\n');
+        res.push_str(&messages.get(related));
     }
 
     Ok(res)
@@ -149,5 +210,10 @@ async fn get_asoul_cnki_from_str(s: &str) -> Result<String> {
 #[ignore]
 async fn test_get_asoul_cnki() {
     let s = "我把泪水搜集，暴晒在阳光下，不知道有没有到达然然哪里。";
-    assert!(get_asoul_cnki_from_str(s).await.unwrap().contains("辈咯立"));
+    let http = HttpClient::new(&Default::default()).unwrap();
+    let config = crate::i18n::Config::default();
+    assert!(get_asoul_cnki_from_str(&http, &config.for_group(None), s)
+        .await
+        .unwrap()
+        .contains("辈咯立"));
 }
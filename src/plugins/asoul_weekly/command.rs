@@ -1,23 +1,26 @@
+use crate::i18n::Messages;
 use crate::prelude::*;
+use crate::report::UserError;
+use crate::{HttpClient, Text};
 use chrono::{DateTime, Utc};
-use reqwest::Client;
 use serde_json::json;
 use std::collections::BTreeMap;
 use std::fmt::Write;
 
+/// 后端返回非 200 时出错：带有 `error` 字段的是后端拒绝了请求，原样回复给用户；否则是后端出错
 macro_rules! errorcheck {
     ($response:expr) => {{
         let status = $response.status();
+        crate::metrics::METRICS.http_status("asoul_weekly", status.as_str());
         if status != reqwest::StatusCode::OK {
             let json: serde_json::Value = $response.json().await?;
             info!("请求返回 json：{:?}", json);
-            if let Some(r) = json.get("error").cloned() {
-                if let Some(s) = r.as_str() {
-                    return Ok(s.to_string());
-                }
+            if let Some(error) = json.get("error").and_then(|e| e.as_str()) {
+                return Err(
+                    UserError::new(Text::new("weekly.rejected").arg("error", error)).into(),
+                );
             }
-            error!("请求错误：{:?}", json);
-            return Ok(json.to_string());
+            bail!("请求错误 {}：{}", status, json);
         }
     }};
 }
@@ -32,8 +35,21 @@ pub enum Command {
     Kpi { date: DateTime<Utc> },
 }
 impl Command {
-    pub async fn execute(self, base_url: &str) -> Result<String> {
-        let client = Client::new();
+    /// 是否修改周报分类，需要记录审计日志
+    pub fn changes_category(&self) -> bool {
+        matches!(
+            self,
+            Command::Add { .. } | Command::Delete { .. } | Command::Change { .. }
+        )
+    }
+
+    pub async fn execute(
+        self,
+        base_url: &str,
+        http: &HttpClient,
+        messages: &Messages<'_>,
+    ) -> Result<String> {
+        let client = http.client();
         match &self {
             Command::Add { id, category } => {
                 let response = client
@@ -42,7 +58,11 @@ impl Command {
                     .send()
                     .await?;
                 errorcheck!(response);
-                Ok(format!("新建 id {} 为 {} 成功", id, category))
+                Ok(messages.get(
+                    Text::new("weekly.added")
+                        .arg("id", id)
+                        .arg("category", category),
+                ))
             }
             Command::Delete { id } => {
                 let response = client
@@ -50,7 +70,7 @@ impl Command {
                     .send()
                     .await?;
                 errorcheck!(response);
-                Ok(format!("删除 id {} 分类成功", id))
+                Ok(messages.get(Text::new("weekly.deleted").arg("id", id)))
             }
             Command::Change { id, category } => {
                 let response = client
@@ -59,12 +79,15 @@ impl Command {
                     .send()
                     .await?;
                 errorcheck!(response);
-                Ok(format!("修改 id {} 为 {} 成功", id, category))
+                Ok(messages.get(
+                    Text::new("weekly.changed")
+                        .arg("id", id)
+                        .arg("category", category),
+                ))
             }
             Command::Query { id } => {
-                let response = client
+                let response = http
                     .get(format!("{}/items/{}/category", base_url, id))
-                    .send()
                     .await?;
                 errorcheck!(response);
                 #[derive(Debug, Deserialize)]
@@ -72,15 +95,18 @@ impl Command {
                     category: Option<String>,
                 }
                 let ret: R = response.json().await?;
-                Ok(match ret.category {
-                    Some(cat) => format!("id {} 当前分类为 【{}】", id, cat),
-                    None => format!("id {} 当前尚未分类", id),
-                })
+                let text = match ret.category {
+                    Some(cat) => Text::new("weekly.category")
+                        .arg("id", id)
+                        .arg("category", cat),
+                    None => Text::new("weekly.uncategorized").arg("id", id),
+                };
+                Ok(messages.get(text))
             }
             Command::Summary { date } => {
                 let t = date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
                 let url = format!("{}/summary?t={}", base_url, t);
-                let response = client.get(url).send().await?;
+                let response = http.get(url).await?;
                 errorcheck!(response);
 
                 let summary: BTreeMap<String, Vec<String>> = response.json().await?;
@@ -104,7 +130,7 @@ impl Command {
             Command::Kpi { date } => {
                 let t = date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
                 let url = format!("{}/kpi?t={}", base_url, t);
-                let response = client.get(url).send().await?;
+                let response = http.get(url).await?;
                 errorcheck!(response);
 
                 #[derive(Debug, Deserialize)]
@@ -115,7 +141,10 @@ impl Command {
                 let kpi: Vec<Row> = response.json().await?;
                 let mut message = String::new();
                 for row in kpi {
-                    writeln!(message, "【{}】筛选了 【{}】 个", row.name, row.times)?;
+                    let text = Text::new("weekly.kpi_row")
+                        .arg("name", row.name)
+                        .arg("times", row.times);
+                    writeln!(message, "{}", messages.get(text))?;
                 }
                 Ok(message)
             }
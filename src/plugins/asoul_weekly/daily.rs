@@ -1,4 +1,7 @@
-use crate::{prelude::*, Config};
+use super::{AsoulWeekly, DAILY};
+use crate::plugins::Plugin;
+use crate::router;
+use crate::{prelude::*, Middleware, Permissions, SharedConfig, Text};
 use biliapi::Request;
 
 async fn main() -> Result<i64> {
@@ -26,7 +29,14 @@ async fn main() -> Result<i64> {
     Ok(r.aid)
 }
 
-pub async fn generate_daily(msg: GroupMessage, bot: Bot, config: Data<Config>) -> Result<()> {
+pub async fn generate_daily(
+    msg: GroupMessage,
+    bot: Bot,
+    config: Data<SharedConfig>,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    let config = config.load();
     if !config
         .asoul_weekly
         .allow_groups
@@ -34,21 +44,29 @@ pub async fn generate_daily(msg: GroupMessage, bot: Bot, config: Data<Config>) -
     {
         return Ok(());
     }
-
-    if !config.admins.contains(&msg.sender.id) {
+    if router::route(&config.router, &DAILY, &msg.message).is_none() {
         return Ok(());
     }
-
-    msg.reply("开始生成日报", &bot).await?;
-    match main().await {
-        Ok(aid) => {
-            msg.reply(format!("已生成今日日报，aid={}", aid), &bot)
-                .await?;
-        }
-        Err(e) => {
-            msg.reply(format!("生成日报发生错误：{:?}", e), &bot)
-                .await?;
-        }
+    if !perms.allows(&msg, AsoulWeekly.name(), DAILY.role) {
+        return Ok(());
     }
+
+    let messages = config.messages.for_group(Some(msg.sender.group.id));
+    let handler = async {
+        msg.reply(messages.get("weekly.daily_started"), &bot)
+            .await?;
+        let result = main().await.context("生成日报失败");
+        let summary = result.as_ref().map(|aid| format!("aid={}", aid));
+        mw.audit()
+            .record(&msg, AsoulWeekly.name(), DAILY.name, &summary);
+        let aid = result?;
+        msg.reply(
+            messages.get(Text::new("weekly.daily_done").arg("aid", aid)),
+            &bot,
+        )
+        .await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, AsoulWeekly.name(), handler).await;
     Ok(())
 }
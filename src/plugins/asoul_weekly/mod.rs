@@ -4,7 +4,10 @@ use chrono::{Duration, Utc};
 use regex::Regex;
 use std::collections::HashSet;
 
+use super::{CommandInfo, Plugin};
 use crate::prelude::*;
+use crate::router::{self, Args};
+use crate::{HttpClient, Middleware, Permissions, Role};
 
 mod command;
 mod daily;
@@ -12,19 +15,106 @@ mod utils;
 
 use command::Command;
 
-pub fn init(bot: Bot) {
-    bot.handler(on_message)
-        .command("生成日报", daily::generate_daily);
+pub struct AsoulWeekly;
+
+const QUERY: CommandInfo = CommandInfo {
+    name: "分类",
+    aliases: &["?", "？"],
+    has_args: true,
+    role: Role::Member,
+    examples: &["分类 BV1AR4y147gy", "? 587803185410047312"],
+};
+const CHANGE: CommandInfo = CommandInfo {
+    name: "修改分类",
+    aliases: &[],
+    has_args: true,
+    role: Role::Member,
+    examples: &[
+        "修改分类 BV1AR4y147gy 翻唱",
+        "修改分类 BV1AR4y147gy +其他",
+        "修改分类 BV1AR4y147gy 删除",
+    ],
+};
+const SUMMARY: CommandInfo = CommandInfo {
+    name: "归档",
+    aliases: &["今日归档", "今天归档", "昨天归档", "昨日归档", "前天归档"],
+    has_args: false,
+    role: Role::Member,
+    examples: &["归档", "昨天归档"],
+};
+const KPI: CommandInfo = CommandInfo {
+    name: "kpi",
+    aliases: &["今日kpi", "今天kpi", "昨天kpi", "昨日kpi", "前天kpi"],
+    has_args: false,
+    role: Role::Member,
+    examples: &["kpi", "昨天kpi"],
+};
+const SHORTCUT: CommandInfo = CommandInfo {
+    name: "快捷分类",
+    aliases: &[],
+    has_args: false,
+    role: Role::Member,
+    examples: &[
+        "https://t.bilibili.com/548810564605393067 +",
+        "https://b23.tv/oNcAbk -",
+    ],
+};
+const DAILY: CommandInfo = CommandInfo {
+    name: "生成日报",
+    aliases: &[],
+    has_args: false,
+    role: Role::SuperAdmin,
+    examples: &["生成日报"],
+};
+
+impl Plugin for AsoulWeekly {
+    fn name(&self) -> &'static str {
+        "asoul_weekly"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[QUERY, CHANGE, SUMMARY, KPI, SHORTCUT, DAILY]
+    }
+
+    fn available(&self, config: &crate::Config, group: Option<QQ>) -> bool {
+        group.is_some_and(|group| config.asoul_weekly.allow_groups.contains(&group))
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_message).handler(daily::generate_daily);
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// asoul_weekly 的链接
     url: String,
     allow_groups: HashSet<QQ>,
 }
 
-async fn on_message(msg: GroupMessage, config: Data<crate::Config>, bot: Bot) -> Result<()> {
+impl Config {
+    /// 检查配置，返回发现的问题
+    pub fn check(&self) -> Vec<String> {
+        match reqwest::Url::parse(&self.url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => vec![],
+            Ok(url) => vec![format!(
+                "asoul_weekly.url 的协议 {} 无法访问，需要是 http 或 https",
+                url.scheme()
+            )],
+            Err(e) => vec![format!("asoul_weekly.url 不是合法的链接：{}", e)],
+        }
+    }
+}
+
+async fn on_message(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+    http: Data<HttpClient>,
+) -> Result<()> {
+    let config = perms.config().load();
     if !config
         .asoul_weekly
         .allow_groups
@@ -32,123 +122,93 @@ async fn on_message(msg: GroupMessage, config: Data<crate::Config>, bot: Bot) ->
     {
         return Ok(());
     }
+    if !perms.allows(&msg, AsoulWeekly.name(), Role::Member) {
+        return Ok(());
+    }
 
-    match parse_message(msg.as_message().to_string().as_str().trim()).await {
-        Ok(Some(cmd)) => {
-            debug!("消息 {:?} 匹配成功: {:?}", msg, cmd);
-            let reply = cmd.execute(&config.asoul_weekly.url).await?;
-            msg.reply(reply, &bot).await?;
-        }
-        Ok(None) => {
+    let handler = async {
+        let cmd = match parse_message(&http, &config.router, &msg.message).await? {
+            Some(cmd) => cmd,
             // 没匹配到
+            None => return Ok(()),
+        };
+        debug!("消息 {:?} 匹配成功: {:?}", msg, cmd);
+        let changes_category = cmd.changes_category();
+        let messages = config.messages.for_group(Some(msg.sender.group.id));
+        let reply = cmd
+            .execute(&config.asoul_weekly.url, &http, &messages)
+            .await;
+        if changes_category {
+            mw.audit()
+                .record(&msg, AsoulWeekly.name(), CHANGE.name, &reply);
         }
-        Err(e) => {
-            // 匹配过程中发生错误，需要打回客户端
-            warn!("匹配发生错误: {:?}", e);
-            msg.reply(format!("发生错误: {:?}", e), &bot).await?;
-        }
-    }
-
+        msg.reply(reply?, &bot).await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, AsoulWeekly.name(), handler).await;
     Ok(())
 }
 
-async fn parse_message(msg: &str) -> Result<Option<Command>> {
+async fn parse_message(
+    http: &HttpClient,
+    config: &router::Config,
+    chain: &MessageChain,
+) -> Result<Option<Command>> {
     // 查询分类
-    if let Ok(cmd) = parse_query_category(msg) {
-        return Ok(Some(cmd));
+    if let Some(mut args) = router::route(config, &QUERY, chain) {
+        let id = utils::normalize_id(args.text()?);
+        args.finish()?;
+        return Ok(Some(Command::Query { id }));
     }
-
-    match msg {
-        msg if msg.starts_with("分类") || msg.starts_with('?') => {
-            let command = parse_query_category(msg)?;
-            Ok(Some(command))
-        }
-        // 归档
-        msg if msg.ends_with("归档") => {
-            let date = match msg {
-                "今日归档" | "今天归档" | "归档" => Utc::now(),
-                "昨天归档" | "昨日归档" => Utc::now() - Duration::days(1),
-                "前天归档" => Utc::now() - Duration::days(2),
-                _ => return Ok(None),
-            };
-            Ok(Some(Command::Summary { date }))
-        }
-        // kpi
-        msg if msg.to_lowercase().ends_with("kpi") => {
-            let date = match msg.to_lowercase().as_str() {
-                "今日kpi" | "今天kpi" | "kpi" => Utc::now(),
-                "昨天kpi" | "昨日kpi" => Utc::now() - Duration::days(1),
-                "前天kpi" => Utc::now() - Duration::days(2),
-                _ => return Ok(None),
-            };
-            Ok(Some(Command::Kpi { date }))
-        }
-        // 修改分类
-        msg if msg.starts_with("修改分类") => {
-            let command = parse_change_category(msg)?;
-            Ok(Some(command))
-        }
-        // 缩写
-        msg => parse_shortcut(msg).await,
+    // 修改分类
+    if let Some(mut args) = router::route(config, &CHANGE, chain) {
+        return parse_change_category(&mut args).map(Some);
     }
-}
-
-fn parse_query_category(msg: &str) -> Result<Command> {
-    lazy_static::lazy_static! {
-        static ref PATTERN: Regex = Regex::new(r"^(分类|\?|？)\s+(?P<id>\w+)$").unwrap();
+    // 归档
+    if let Some(args) = router::route(config, &SUMMARY, chain) {
+        let date = date_of(args.alias());
+        return Ok(Some(Command::Summary { date }));
     }
-    match PATTERN.captures(msg) {
-        Some(cap) => {
-            let id = cap
-                .name("id")
-                .ok_or_else(|| anyhow!("获取分类缺少 ID"))?
-                .as_str()
-                .to_string();
-            Ok(Command::Query { id })
-        }
-        None => {
-            bail!("格式错误，格式需要是：\n分类 BVxxxxxxx")
-        }
+    // kpi
+    if let Some(args) = router::route(config, &KPI, chain) {
+        let date = date_of(args.alias());
+        return Ok(Some(Command::Kpi { date }));
     }
+    // 缩写
+    parse_shortcut(http, chain.to_string().trim()).await
 }
 
-/// 处理以 修改分类 开始的指令
-fn parse_change_category(msg: &str) -> Result<Command> {
-    lazy_static::lazy_static! {
-        static ref PATTERN: Regex = Regex::new(r"^修改分类\s+(\w+)\s+([^\s]+)$").unwrap();
+/// 根据 `昨天归档`、`前天kpi` 等别名决定日期
+fn date_of(alias: &str) -> chrono::DateTime<Utc> {
+    let days = if alias.starts_with('昨') {
+        1
+    } else if alias.starts_with('前') {
+        2
+    } else {
+        0
+    };
+    Utc::now() - Duration::days(days)
+}
+
+/// 修改分类的参数：id 以及分类，`+分类` 表示追加，`删除` 或者 `-` 表示移出周报
+fn parse_change_category(args: &mut Args) -> Result<Command> {
+    let id = utils::normalize_id(args.text()?);
+    let category = args.text()?;
+    if !args.is_empty() {
+        return Err(args.usage_error());
     }
-    match PATTERN.captures(msg) {
-        Some(cap) => {
-            let id = cap
-                .get(1)
-                .ok_or_else(|| anyhow!("修改分类缺少 ID"))?
-                .as_str()
-                .to_string();
-            let category = cap.get(2).ok_or_else(|| anyhow!("缺少分类"))?.as_str();
-
-            match category {
-                "删除" | "-" => Ok(Command::Delete { id }),
-                cat if cat.starts_with('+') => {
-                    let cat = cat.trim_start_matches('+');
-                    Ok(Command::Add {
-                        id,
-                        category: cat.to_string(),
-                    })
-                }
-                cat => Ok(Command::Change {
-                    id,
-                    category: cat.to_string(),
-                }),
-            }
-        }
-        None => {
-            bail!("格式错误，格式需要是：\n修改分类 BVxxxxxxxx 翻唱/删除")
-        }
+    match category.as_str() {
+        "删除" | "-" => Ok(Command::Delete { id }),
+        cat if cat.starts_with('+') => Ok(Command::Add {
+            id,
+            category: cat.trim_start_matches('+').to_string(),
+        }),
+        _ => Ok(Command::Change { id, category }),
     }
 }
 
 /// 尝试按照简写形式进行匹配
-async fn parse_shortcut(msg: &str) -> Result<Option<Command>> {
+async fn parse_shortcut(http: &HttpClient, msg: &str) -> Result<Option<Command>> {
     lazy_static::lazy_static! {
         static ref URL_REGEXP: Regex =
             Regex::new(r"https://((b23\.tv|.+\.bilibili.com)/\w+)").unwrap();
@@ -165,7 +225,7 @@ async fn parse_shortcut(msg: &str) -> Result<Option<Command>> {
         None => return Ok(None),
     };
     //
-    let id = utils::get_redirected_id(url).await?;
+    let id = utils::get_redirected_id(http, url).await?;
     let cmd = if msg.ends_with('+') {
         Command::Add {
             id,
@@ -181,10 +241,26 @@ async fn parse_shortcut(msg: &str) -> Result<Option<Command>> {
 mod tests {
     use super::*;
 
+    async fn parse(msg: &str) -> Result<Option<Command>> {
+        let http = HttpClient::new(&Default::default())?;
+        parse_message(&http, &Default::default(), &MessageChain::new().text(msg)).await
+    }
+
+    #[test]
+    fn test_config_check() {
+        let config = |url: &str| Config {
+            url: url.to_string(),
+            allow_groups: HashSet::new(),
+        };
+        assert!(config("https://example.com").check().is_empty());
+        assert_eq!(config("ftp://example.com").check().len(), 1);
+        assert_eq!(config("example.com").check().len(), 1);
+    }
+
     #[tokio::test]
     async fn test_shortcut() -> Result<()> {
         assert_eq!(
-            parse_message("https://b23.tv/oNcAbk +").await?,
+            parse("https://b23.tv/oNcAbk +").await?,
             Some(Command::Add {
                 id: "581071094762952016".to_string(),
                 category: "动态".to_string()
@@ -192,7 +268,7 @@ mod tests {
         );
 
         assert_eq!(
-            parse_message("https://b23.tv/oNcAbk [@人] +").await?,
+            parse("https://b23.tv/oNcAbk [@人] +").await?,
             Some(Command::Add {
                 id: "581071094762952016".to_string(),
                 category: "动态".to_string()
@@ -200,7 +276,7 @@ mod tests {
         );
 
         assert_eq!(
-            parse_message("[@人] https://b23.tv/oNcAbk +").await?,
+            parse("[@人] https://b23.tv/oNcAbk +").await?,
             Some(Command::Add {
                 id: "581071094762952016".to_string(),
                 category: "动态".to_string()
@@ -208,21 +284,21 @@ mod tests {
         );
 
         assert_eq!(
-            parse_message("https://b23.tv/oNcAbk   -").await?,
+            parse("https://b23.tv/oNcAbk   -").await?,
             Some(Command::Delete {
                 id: "581071094762952016".to_string(),
             })
         );
         assert_eq!(
-            parse_message("https://t.bilibili.com/548810564605393067  +").await?,
+            parse("https://t.bilibili.com/548810564605393067  +").await?,
             Some(Command::Add {
                 id: "548810564605393067".to_string(),
                 category: "动态".to_string()
             })
         );
-        assert_eq!(parse_message("https://example.com/123  +").await?, None);
+        assert_eq!(parse("https://example.com/123  +").await?, None);
 
-        assert!(parse_message("https://b23.tv/bjPZgml  +").await.is_err());
+        assert!(parse("https://b23.tv/bjPZgml  +").await.is_err());
 
         Ok(())
     }
@@ -230,7 +306,7 @@ mod tests {
     #[tokio::test]
     async fn test_change_category() -> Result<()> {
         assert_eq!(
-            parse_message("修改分类  BV1AR4y147gy  其他").await?,
+            parse("修改分类  BV1AR4y147gy  其他").await?,
             Some(Command::Change {
                 id: "BV1AR4y147gy".to_string(),
                 category: "其他".to_string()
@@ -238,7 +314,7 @@ mod tests {
         );
 
         assert_eq!(
-            parse_message("修改分类  BV1AR4y147gy  +其他").await?,
+            parse("修改分类  BV1AR4y147gy  +其他").await?,
             Some(Command::Add {
                 id: "BV1AR4y147gy".to_string(),
                 category: "其他".to_string()
@@ -246,21 +322,21 @@ mod tests {
         );
 
         assert_eq!(
-            parse_message("修改分类  BV1AR4y147gy -").await?,
+            parse("修改分类  BV1AR4y147gy -").await?,
             Some(Command::Delete {
                 id: "BV1AR4y147gy".to_string(),
             })
         );
 
         assert_eq!(
-            parse_message("修改分类  BV1AR4y147gy 删除").await?,
+            parse("修改分类  BV1AR4y147gy 删除").await?,
             Some(Command::Delete {
                 id: "BV1AR4y147gy".to_string(),
             })
         );
 
         assert_eq!(
-            parse_message("修改分类  BV1AR4y147gy  +A-SOUL").await?,
+            parse("修改分类  BV1AR4y147gy  +A-SOUL").await?,
             Some(Command::Add {
                 id: "BV1AR4y147gy".to_string(),
                 category: "A-SOUL".to_string()
@@ -268,57 +344,81 @@ mod tests {
         );
 
         assert_eq!(
-            parse_message("修改分类  BV1AR4y147gy  A-SOUL").await?,
+            parse("修改分类  BV1AR4y147gy  A-SOUL").await?,
             Some(Command::Change {
                 id: "BV1AR4y147gy".to_string(),
                 category: "A-SOUL".to_string()
             })
         );
 
+        assert_eq!(
+            parse("修改分类 av170001 其他").await?,
+            Some(Command::Change {
+                id: "BV17x411w7KC".to_string(),
+                category: "其他".to_string()
+            })
+        );
+
         Ok(())
     }
 
     #[tokio::test]
     async fn test_query_category() -> Result<()> {
         assert_eq!(
-            parse_message("分类  BV1AR4y147gy").await?,
+            parse("分类  BV1AR4y147gy").await?,
             Some(Command::Query {
                 id: "BV1AR4y147gy".to_string(),
             })
         );
         assert_eq!(
-            parse_message("分类  587803185410047312").await?,
+            parse("分类  587803185410047312").await?,
             Some(Command::Query {
                 id: "587803185410047312".to_string(),
             })
         );
         assert_eq!(
-            parse_message("分类 587803185410047312").await?,
+            parse("分类 587803185410047312").await?,
             Some(Command::Query {
                 id: "587803185410047312".to_string(),
             })
         );
         assert_eq!(
-            parse_message("? 587803185410047312").await?,
+            parse("? 587803185410047312").await?,
             Some(Command::Query {
                 id: "587803185410047312".to_string(),
             })
         );
-        assert_eq!(parse_message("？啥").await?, None);
+        // 视频统一为 BV 号
+        for msg in [
+            "分类 av170001",
+            "? https://www.bilibili.com/video/av170001?p=1",
+            "分类 https://b23.tv/BV17x411w7KC",
+        ] {
+            assert_eq!(
+                parse(msg).await?,
+                Some(Command::Query {
+                    id: "BV17x411w7KC".to_string(),
+                })
+            );
+        }
+        assert_eq!(parse("？啥").await?, None);
+
+        let e = parse("分类").await.unwrap_err();
+        assert_eq!(e.to_string(), "用法：分类 视频或动态id");
+        assert!(parse("分类 BV1AR4y147gy 其他").await.is_err());
         Ok(())
     }
 
     #[tokio::test]
     async fn test_other_command() -> Result<()> {
-        assert!(matches!(
-            parse_message("kpi").await?,
-            Some(Command::Kpi { .. })
-        ));
+        assert!(matches!(parse("kpi").await?, Some(Command::Kpi { .. })));
 
         assert!(matches!(
-            parse_message("归档").await?,
+            parse("归档").await?,
             Some(Command::Summary { .. })
         ));
+        assert!(matches!(parse("昨天KPI").await?, Some(Command::Kpi { .. })));
+        assert_eq!(parse("大后天归档").await?, None);
 
         Ok(())
     }
@@ -1,8 +1,19 @@
 use anyhow::*;
 use regex::Regex;
 
+use crate::report::UserError;
+use crate::{bvid, HttpClient};
+
+/// 周报中的视频用 BV 号记录：av 号、BV 号以及视频链接统一转换为 BV 号，其他的 id（动态 id）保持不变
+pub fn normalize_id(id: String) -> String {
+    match bvid::find(&id) {
+        Some(video) => video.bvid(),
+        None => id,
+    }
+}
+
 /// 从 b23 短链或者 t.bilibili.com 长链解析出动态 id
-pub async fn get_redirected_id(url: &str) -> Result<String> {
+pub async fn get_redirected_id(http: &HttpClient, url: &str) -> Result<String> {
     lazy_static::lazy_static! {
         static ref ID_REGEXP: Regex =
             Regex::new(r"https://((t\.bilibili\.com)|(m\.bilibili\.com/dynamic))/(?P<did>\d+)")
@@ -16,19 +27,18 @@ pub async fn get_redirected_id(url: &str) -> Result<String> {
         }
     }
     if !url.starts_with("https://b23.tv/") {
-        bail!("链接不识别，应该是 b23.tv 短链或者 t.bilibili.com 长链");
+        bail!(UserError::new("weekly.bad_link"));
     }
     info!("进行重定向，url = {}", url);
 
-    let client = reqwest::ClientBuilder::new()
-        .redirect(reqwest::redirect::Policy::none())
-        .build()?;
-    let response = client.get(url).send().await?;
+    let response = http.no_redirect().get(url).send().await;
+    crate::metrics::METRICS.http_response("bilibili", &response);
+    let response = response?;
     let location = match response.headers().get("location") {
         Some(header) => header.to_str()?,
         None => {
             warn!("重定向失败：response = {:?}", response);
-            bail!("短链重定向失败");
+            bail!(UserError::new("weekly.redirect_failed"));
         }
     };
     info!("location = {:?}", location);
@@ -38,28 +48,38 @@ pub async fn get_redirected_id(url: &str) -> Result<String> {
             return Ok(id.to_string());
         }
     }
-    bail!("未在重定向后的链接内解析出动态 id");
+    bail!(UserError::new("weekly.no_dynamic_id"));
 }
 
 #[tokio::test]
 async fn test_get_redirected_id() {
     pretty_env_logger::try_init().ok();
+    let http = HttpClient::new(&Default::default()).unwrap();
     assert_eq!(
-        get_redirected_id("https://t.bilibili.com/581071094762952016")
+        get_redirected_id(&http, "https://t.bilibili.com/581071094762952016")
             .await
             .unwrap(),
         "581071094762952016"
     );
     assert_eq!(
-        get_redirected_id("https://m.bilibili.com/dynamic/581071094762952016?share_")
-            .await
-            .unwrap(),
+        get_redirected_id(
+            &http,
+            "https://m.bilibili.com/dynamic/581071094762952016?share_"
+        )
+        .await
+        .unwrap(),
         "581071094762952016"
     );
     assert_eq!(
-        get_redirected_id("https://b23.tv/oNcAbk").await.unwrap(),
+        get_redirected_id(&http, "https://b23.tv/oNcAbk")
+            .await
+            .unwrap(),
         "581071094762952016"
     );
-    assert!(get_redirected_id("https://example.com").await.is_err());
-    assert!(get_redirected_id("https://b23.tv/AOLrjh").await.is_err());
+    assert!(get_redirected_id(&http, "https://example.com")
+        .await
+        .is_err());
+    assert!(get_redirected_id(&http, "https://b23.tv/AOLrjh")
+        .await
+        .is_err());
 }
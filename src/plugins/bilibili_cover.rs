@@ -1,35 +1,89 @@
 //! 获取 bilibili 封面
+use super::{CommandInfo, Plugin};
+use crate::metrics::METRICS;
 use crate::prelude::*;
+use crate::router::{self, Args};
+use crate::{bvid, HttpClient, Middleware, Permissions, Role};
 use biliapi::requests::Request;
-use lazy_static::lazy_static;
-use once_cell::sync::Lazy;
-use regex::Regex;
 
-pub fn init(bot: Bot) {
-    bot.command("封面", on_message::<FriendMessage>)
-        .command("封面", on_message::<GroupMessage>);
+pub struct BilibiliCover;
+
+const COVER: CommandInfo = CommandInfo {
+    name: "封面",
+    aliases: &[],
+    has_args: true,
+    role: Role::Member,
+    examples: &["封面 BV1AR4y147gy", "封面 av170001"],
+};
+
+impl Plugin for BilibiliCover {
+    fn name(&self) -> &'static str {
+        "bilibili_cover"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[COVER]
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_message::<FriendMessage>)
+            .handler(on_message::<GroupMessage>);
+    }
 }
 
-async fn on_message<T: Conversation>(msg: T, bot: Bot) -> Result<()> {
-    // 匹配全部 bv 号
-    lazy_static! {
-        static ref BV_REGEX: Regex = Regex::new(r"BV[\dA-Za-z]+").unwrap();
+async fn on_message<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+    http: Data<HttpClient>,
+) -> Result<()> {
+    let args = match router::route(&perms.config().load().router, &COVER, msg.as_message()) {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if !perms.allows(&msg, BilibiliCover.name(), COVER.role) {
+        return Ok(());
+    }
+    if !mw.admit(&msg, BilibiliCover.name(), &bot).await? {
+        return Ok(());
     }
+    mw.run(
+        &msg,
+        &bot,
+        BilibiliCover.name(),
+        covers(&msg, &bot, &http, args),
+    )
+    .await;
+    Ok(())
+}
 
+/// 回复参数中全部视频的封面
+async fn covers<T: Conversation + ConversationExt + Sync>(
+    msg: &T,
+    bot: &Bot,
+    http: &HttpClient,
+    mut args: Args,
+) -> Result<()> {
     info!("封面命令触发");
-    let client = Lazy::<reqwest::Client>::new(|| biliapi::connection::new_client().unwrap());
-    let s = msg.as_message().to_string();
+    let s = args.rest();
+    let videos = bvid::find_iter(&s).map(|m| m.video).collect::<Vec<_>>();
+    if videos.is_empty() {
+        return Err(args.usage_error());
+    }
 
-    for m in BV_REGEX.find_iter(&s) {
-        let bv = m.as_str();
+    for video in videos {
+        let bv = video.bvid();
         info!("寻找视频 {} 封面", bv);
-        let video_info = biliapi::requests::VideoInfo::request(&client, bv.to_string()).await?;
+        let video_info = biliapi::requests::VideoInfo::request(http.client(), bv.clone()).await;
+        METRICS.api_result("bilibili", &video_info);
+        let video_info = video_info?;
         info!(
             "视频 {} ({}) 封面为 {}",
             bv, video_info.title, video_info.cover_url
         );
         let reply = MessageChain::new().image_url(video_info.cover_url);
-        msg.reply(reply, &bot).await?;
+        msg.reply(reply, bot).await?;
     }
 
     Ok(())
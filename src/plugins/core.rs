@@ -1,46 +1,397 @@
 //! 核心模块
 
+use super::{CommandInfo, Plugin};
 use crate::prelude::*;
-use crate::Config;
+use crate::router::{self, Args};
+use crate::{Middleware, Permissions, Role, Text};
 
-pub fn init(bot: Bot) {
-    bot.command("ping", ping_pong::<FriendMessage>)
-        .command("ping", ping_pong::<GroupMessage>)
-        .command("reload", reload::<FriendMessage>)
-        .command("reload", reload::<GroupMessage>);
+pub struct Core;
+
+const PING: CommandInfo = CommandInfo {
+    name: "ping",
+    aliases: &[],
+    has_args: false,
+    role: Role::Member,
+    examples: &["ping"],
+};
+const RELOAD: CommandInfo = CommandInfo {
+    name: "reload",
+    aliases: &[],
+    has_args: false,
+    role: Role::SuperAdmin,
+    examples: &["reload"],
+};
+const ENABLE: CommandInfo = CommandInfo {
+    name: "开启",
+    aliases: &[],
+    has_args: true,
+    role: Role::GroupAdmin,
+    examples: &["开启 枝网查重"],
+};
+const DISABLE: CommandInfo = CommandInfo {
+    name: "关闭",
+    aliases: &[],
+    has_args: true,
+    role: Role::GroupAdmin,
+    examples: &["关闭 关键词回复"],
+};
+const SET_ROLE: CommandInfo = CommandInfo {
+    name: "设置权限",
+    aliases: &[],
+    has_args: true,
+    role: Role::GroupAdmin,
+    examples: &["设置权限 @某人 信任", "设置权限 123456 封禁"],
+};
+const QUERY_ROLE: CommandInfo = CommandInfo {
+    name: "查看权限",
+    aliases: &[],
+    has_args: true,
+    role: Role::Member,
+    examples: &["查看权限", "查看权限 @某人"],
+};
+
+const AUDIT: CommandInfo = CommandInfo {
+    name: "审计",
+    aliases: &[],
+    has_args: true,
+    role: Role::SuperAdmin,
+    examples: &["审计 最近20", "审计 导出 最近50"],
+};
+
+/// `审计` 默认显示的条数
+const DEFAULT_AUDIT_LIMIT: usize = 20;
+/// `审计` 最多显示的条数
+const MAX_AUDIT_LIMIT: usize = 200;
+
+impl Plugin for Core {
+    fn name(&self) -> &'static str {
+        "core"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[PING, RELOAD, ENABLE, DISABLE, SET_ROLE, QUERY_ROLE, AUDIT]
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(ping_pong::<FriendMessage>)
+            .handler(ping_pong::<GroupMessage>)
+            .handler(reload::<FriendMessage>)
+            .handler(reload::<GroupMessage>)
+            .handler(enable_plugin)
+            .handler(disable_plugin)
+            .handler(set_role)
+            .handler(query_role)
+            .handler(audit::<FriendMessage>)
+            .handler(audit::<GroupMessage>);
+    }
 }
 
 /// ping-pong!
-async fn ping_pong<T: Conversation>(msg: T, bot: Bot) -> Result<()> {
-    if msg.as_message().to_string().trim() == "ping" {
-        let resp = msg.reply("pong", &bot).await?;
+async fn ping_pong<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    if router::route(&perms.config().load().router, &PING, msg.as_message()).is_none() {
+        return Ok(());
+    }
+    if !perms.allows(&msg, Core.name(), PING.role) {
+        return Ok(());
+    }
+    let handler = async {
+        let config = perms.config().load();
+        let pong = config.messages.for_group(msg.group_id()).get("core.pong");
+        let resp = msg.reply(pong, &bot).await?;
         sleep(Duration::from_secs(5)).await;
         bot.request(api::recall::Request {
             message_id: resp.message_id,
         })
         .await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Core.name(), handler).await;
+    Ok(())
+}
+
+/// 给管理员加上 reload 信息
+async fn reload<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    if router::route(&perms.config().load().router, &RELOAD, msg.as_message()).is_none() {
+        return Ok(());
+    }
+    if !perms.allows(&msg, Core.name(), RELOAD.role) {
+        return Ok(());
     }
 
+    let handler = async {
+        info!("reload config");
+        let config = perms.config();
+        let result = config.reload();
+        let summary = result
+            .as_ref()
+            .map(|changes| format!("{} 项配置变化", changes.len()));
+        mw.audit().record(&msg, Core.name(), RELOAD.name, &summary);
+        let reply = match result {
+            Ok(changes) => {
+                info!("reload 成功，变化：{:?}", changes);
+                debug!("config = {:?}", config.load());
+                if changes.is_empty() {
+                    Text::new("core.reload_unchanged")
+                } else {
+                    Text::new("core.reload_changed").arg("changes", changes.join("\n"))
+                }
+            }
+            Err(e) => {
+                error!("reload 失败：{:?}", e);
+                Text::new("core.reload_failed").arg("error", crate::config::describe_error(&e))
+            }
+        };
+        // 使用 reload 之后的配置
+        let config = config.load();
+        msg.reply(config.messages.for_group(msg.group_id()).get(reply), &bot)
+            .await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Core.name(), handler).await;
     Ok(())
 }
 
-/// 给管理员加上 reload 信息
-async fn reload<T: Conversation>(msg: T, bot: Bot, config: Data<Config>) -> Result<Option<String>> {
-    if !config.is_admin(*msg.sender().as_ref()) {
-        return Ok(None);
-    }
-
-    info!("reload config");
-    match crate::Config::new() {
-        Ok(new_config) => {
-            bot.bot_data(new_config);
-            info!("reload 成功");
-            debug!("config = {:?}", config);
-            Ok(Some("reload 成功".to_string()))
-        }
-        Err(e) => {
-            error!("reload 失败：{:?}", e);
-            Ok(Some(format!("reload 失败：{:?}", e)))
-        }
+/// 在本群开启插件
+async fn enable_plugin(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    switch_plugin(msg, bot, perms, mw, &ENABLE, true).await
+}
+
+/// 在本群关闭插件
+async fn disable_plugin(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    switch_plugin(msg, bot, perms, mw, &DISABLE, false).await
+}
+
+async fn switch_plugin(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+    command: &'static CommandInfo,
+    enabled: bool,
+) -> Result<()> {
+    let mut args = match router::route(&perms.config().load().router, command, &msg.message) {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if !perms.allows(&msg, Core.name(), command.role) {
+        return Ok(());
+    }
+    let handler = async {
+        let reply = set_plugin_switch(&msg, &perms, &mut args, command.name, enabled)?;
+        let config = perms.config().load();
+        msg.reply(config.messages.for_group(msg.group_id()).get(reply), &bot)
+            .await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Core.name(), handler).await;
+    Ok(())
+}
+
+fn set_plugin_switch(
+    msg: &GroupMessage,
+    perms: &Permissions,
+    args: &mut Args,
+    command: &str,
+    enabled: bool,
+) -> Result<Text> {
+    let name = args.rest();
+    if name.is_empty() {
+        return Err(args.usage_error());
+    }
+    let group = msg.sender.group.id;
+    let config = perms.config().load();
+    let plugin = match super::lookup(&config.messages.for_group(Some(group)), &name) {
+        Some(plugin) => plugin,
+        None => return Ok(Text::new("core.plugin_not_found").arg("name", name)),
+    };
+    if plugin.name() == Core.name() {
+        return Ok(Text::new("core.core_plugin"));
     }
+
+    perms.switch().set(group, plugin.name(), enabled)?;
+    info!("群 {} {}插件 {}", group.0, command, plugin.name());
+    let key = if enabled {
+        "core.plugin_enabled"
+    } else {
+        "core.plugin_disabled"
+    };
+    Ok(Text::new(key).arg_text("plugin", plugin.description()))
+}
+
+/// 设置权限 @某人 信任
+async fn set_role(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    let mut args = match router::route(&perms.config().load().router, &SET_ROLE, &msg.message) {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if !perms.allows(&msg, Core.name(), SET_ROLE.role) {
+        return Ok(());
+    }
+    let handler = async {
+        let (target, role) = parse_set_role(&mut args)?;
+        let group = msg.sender.group.id;
+        let own = perms.role(&msg);
+        let current = perms.role_in(Some(group), target, false);
+        let config = perms.config().load();
+        let messages = config.messages.for_group(Some(group));
+        let reply = if role >= own || current >= own {
+            messages.get("core.permission_denied")
+        } else {
+            perms.grant(group, target, role)?;
+            info!(
+                "{} 在群 {} 将 {} 设置为 {:?}",
+                msg.sender.id.0, group.0, target.0, role
+            );
+            messages.get(
+                Text::new("core.role_set")
+                    .arg("target", target.0)
+                    .arg("role", messages.get(role.text())),
+            )
+        };
+        msg.reply(reply, &bot).await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Core.name(), handler).await;
+    Ok(())
+}
+
+/// 解析 `设置权限` 的参数：@ 的对象或者 QQ 号，以及角色
+fn parse_set_role(args: &mut Args) -> Result<(QQ, Role)> {
+    let target = args.target()?;
+    let role = Role::parse(&args.text()?).ok_or_else(|| args.usage_error())?;
+    Ok((target, role))
+}
+
+/// 查看自己或者 @ 的人的权限
+async fn query_role(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    let mut args = match router::route(&perms.config().load().router, &QUERY_ROLE, &msg.message) {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if !perms.allows(&msg, Core.name(), QUERY_ROLE.role) {
+        return Ok(());
+    }
+    let handler = async {
+        let role = match args.optional_target() {
+            Some(target) => perms.role_in(Some(msg.sender.group.id), target, false),
+            None => perms.role(&msg),
+        };
+        let config = perms.config().load();
+        let messages = config.messages.for_group(msg.group_id());
+        let reply = Text::new("core.role_query").arg("role", messages.get(role.text()));
+        msg.reply(messages.get(reply), &bot).await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Core.name(), handler).await;
+    Ok(())
+}
+
+/// 解析 `审计` 后面的 `[导出] [最近N]`，返回是否导出为 json 以及条数
+fn parse_audit(args: &str) -> Option<(bool, usize)> {
+    let (export, args) = match args.strip_prefix("导出") {
+        Some(args) => (true, args.trim()),
+        None => (false, args),
+    };
+    let args = args.strip_prefix("最近").unwrap_or(args).trim();
+    let limit = match args {
+        "" => DEFAULT_AUDIT_LIMIT,
+        n => n.parse().ok()?,
+    };
+    Some((export, limit.min(MAX_AUDIT_LIMIT)))
+}
+
+/// 查看或导出审计日志
+async fn audit<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    let mut args = match router::route(&perms.config().load().router, &AUDIT, msg.as_message()) {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if !perms.allows(&msg, Core.name(), AUDIT.role) {
+        return Ok(());
+    }
+    let handler = async {
+        let (export, limit) = parse_audit(&args.rest()).ok_or_else(|| args.usage_error())?;
+        let entries = mw.audit().recent(limit)?;
+        let reply = if export {
+            serde_json::to_string_pretty(&entries)?
+        } else if entries.is_empty() {
+            let config = perms.config().load();
+            config
+                .messages
+                .for_group(msg.group_id())
+                .get("core.audit_empty")
+        } else {
+            let lines = entries.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+            lines.join("\n")
+        };
+        msg.reply(reply, &bot).await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Core.name(), handler).await;
+    Ok(())
+}
+
+#[test]
+fn test_parse_audit() {
+    assert_eq!(parse_audit(""), Some((false, DEFAULT_AUDIT_LIMIT)));
+    assert_eq!(parse_audit("最近5"), Some((false, 5)));
+    assert_eq!(parse_audit("最近 5"), Some((false, 5)));
+    assert_eq!(parse_audit("导出 最近50"), Some((true, 50)));
+    assert_eq!(parse_audit("导出"), Some((true, DEFAULT_AUDIT_LIMIT)));
+    assert_eq!(parse_audit("10000"), Some((false, MAX_AUDIT_LIMIT)));
+    assert_eq!(parse_audit("全部"), None);
+}
+
+#[test]
+fn test_parse_set_role() -> Result<()> {
+    let args = |chain: MessageChain| router::route(&Default::default(), &SET_ROLE, &chain).unwrap();
+
+    let chain = MessageChain::new()
+        .text("设置权限 ")
+        .at(QQ(123))
+        .text(" 信任");
+    assert_eq!(parse_set_role(&mut args(chain))?, (QQ(123), Role::Trusted));
+
+    let chain = MessageChain::new().text("设置权限 123  封禁");
+    assert_eq!(parse_set_role(&mut args(chain))?, (QQ(123), Role::Banned));
+
+    let chain = MessageChain::new().text("设置权限 123 管理员");
+    let e = parse_set_role(&mut args(chain)).unwrap_err();
+    assert_eq!(e.to_string(), "用法：设置权限 @某人 封禁/普通/信任/群管理");
+    Ok(())
 }
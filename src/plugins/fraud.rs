@@ -1,57 +1,102 @@
 //! 生成诈骗链接
+use super::{CommandInfo, Plugin};
+use crate::bvid::{self, Video};
+use crate::i18n::Messages;
 use crate::prelude::*;
-use biliapi::requests::Request;
-use regex::Regex;
+use crate::report::UserError;
+use crate::router;
+use crate::{Config, Middleware, Permissions, Role};
 
-lazy_static::lazy_static! {
-    static ref BV_REGEX: Regex = Regex::new(r"BV[\da-zA-Z]+").unwrap();
-}
+pub struct Fraud;
 
-pub fn init(bot: Bot) {
-    bot.command("诈骗", on_message::<GroupMessage>);
-}
+const FRAUD: CommandInfo = CommandInfo {
+    name: "诈骗",
+    aliases: &[],
+    has_args: false,
+    role: Role::Member,
+    examples: &["诈骗"],
+};
 
-async fn generate_fraud_url(real_bv: String, fake_bv: String) -> Result<String> {
-    info!("目标 BV {real_bv}，虚假 BV {fake_bv}，尝试获取 avid");
+impl Plugin for Fraud {
+    fn name(&self) -> &'static str {
+        "fraud"
+    }
 
-    // 获取真实 av 号
-    use biliapi::requests::VideoInfo;
-    let client = biliapi::connection::new_client()?;
-    let video_info: VideoInfo = VideoInfo::request(&client, real_bv).await?;
-    let real_av = video_info.aid;
-    info!("avid 为 {real_av}");
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[FRAUD]
+    }
 
-    Ok(format!(
-        "https://www.bilibili.com/video/av{real_av}?{fake_bv}"
-    ))
-}
+    fn available(&self, _config: &Config, group: Option<QQ>) -> bool {
+        group.is_some()
+    }
 
-async fn on_message<T: Conversation + Sync>(msg: T, bot: Bot) -> Result<()> {
-    let real_bv: T = msg.prompt("输入诈骗目标 BV", &bot).await?;
-    let real_bv = real_bv.as_message().to_string();
-    if !BV_REGEX.is_match(&real_bv) {
-        bail!("输入不是 BV 号");
+    fn init(&self, bot: Bot) {
+        bot.handler(on_message::<GroupMessage>);
     }
+}
+
+/// 预览显示 `fake`、实际播放 `real` 的链接
+fn fraud_url(real: Video, fake: Video) -> String {
+    format!(
+        "https://www.bilibili.com/video/av{}?{}",
+        real.aid(),
+        fake.bvid()
+    )
+}
 
-    let fake_bv: T = msg.prompt("输入虚假 BV", &bot).await?;
-    let fake_bv: String = fake_bv.as_message().to_string();
-    if !BV_REGEX.is_match(&fake_bv) {
-        bail!("输入不是 BV 号");
+async fn on_message<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    if router::route(&perms.config().load().router, &FRAUD, msg.as_message()).is_none() {
+        return Ok(());
     }
+    if !perms.allows(&msg, Fraud.name(), FRAUD.role) {
+        return Ok(());
+    }
+    if !mw.admit(&msg, Fraud.name(), &bot).await? {
+        return Ok(());
+    }
+    let config = perms.config().load();
+    let messages = config.messages.for_group(msg.group_id());
+    mw.run(&msg, &bot, Fraud.name(), fraud(&msg, &bot, &messages))
+        .await;
+    Ok(())
+}
+
+/// 依次询问目标视频和虚假视频，回复诈骗链接
+async fn fraud<T: Conversation + ConversationExt + Sync>(
+    msg: &T,
+    bot: &Bot,
+    messages: &Messages<'_>,
+) -> Result<()> {
+    let real: T = msg.prompt(messages.get("fraud.prompt_target"), bot).await?;
+    let real = bvid::find(&real.as_message().to_string())
+        .ok_or_else(|| UserError::new("fraud.no_video"))?;
 
-    let url = generate_fraud_url(real_bv, fake_bv).await?;
+    let fake: T = msg.prompt(messages.get("fraud.prompt_fake"), bot).await?;
+    let fake = bvid::find(&fake.as_message().to_string())
+        .ok_or_else(|| UserError::new("fraud.no_video"))?;
+
+    info!("目标视频 {}，虚假视频 {}", real, fake);
+    let url = fraud_url(real, fake);
     debug!("已生成链接：{url}");
-    msg.reply(url, &bot).await?;
+    msg.reply(url, bot).await?;
 
     Ok(())
 }
 
-#[tokio::test]
-async fn test_generate_url() -> Result<()> {
-    let url = generate_fraud_url("BV17b4y1J7ed".to_string(), "BV1nS4y1574h".to_string()).await?;
+#[test]
+fn test_fraud_url() {
+    let video = |s| bvid::find(s).unwrap();
+    assert_eq!(
+        fraud_url(video("av170001"), video("https://b23.tv/BV17b4y1J7ed")),
+        "https://www.bilibili.com/video/av170001?BV17b4y1J7ed"
+    );
     assert_eq!(
-        url,
+        fraud_url(video("BV17b4y1J7ed"), video("BV1nS4y1574h")),
         "https://www.bilibili.com/video/av635700727?BV1nS4y1574h"
     );
-    Ok(())
 }
@@ -0,0 +1,245 @@
+//! 提醒群里的诈骗链接
+//!
+//! [`fraud`](super::fraud) 生成的 `av{真实}?{虚假 BV}` 链接在 QQ 中预览为虚假的视频，打开后播放的却是真实的视频。
+//! 这里检查群消息中的 bilibili 链接，路径中的视频与参数中的视频不一致时回复提醒，指出实际打开的视频。
+//! b23.tv 短链先解析重定向再检查。
+
+use super::Plugin;
+use crate::bvid::{self, Video};
+use crate::metrics::METRICS;
+use crate::prelude::*;
+use crate::{Config, HttpClient, Middleware, Permissions, Role, Text};
+use biliapi::requests::Request;
+use lazy_static::lazy_static;
+use regex::Regex;
+use reqwest::Url;
+
+/// 短链最多跟随的重定向次数
+const MAX_REDIRECTS: usize = 3;
+/// 每条消息最多检查的链接数，避免一条消息触发大量请求
+const MAX_LINKS: usize = 3;
+
+pub struct FraudGuard;
+
+impl Plugin for FraudGuard {
+    fn name(&self) -> &'static str {
+        "fraud_guard"
+    }
+
+    fn available(&self, _config: &Config, group: Option<QQ>) -> bool {
+        group.is_some()
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_message);
+    }
+}
+
+/// 预览显示 `shown`、实际打开 `real` 的链接
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fraud {
+    real: Video,
+    shown: Video,
+}
+
+/// 消息中不重复的 bilibili 链接，可以省略 `https://`
+fn links(s: &str) -> Vec<Url> {
+    lazy_static! {
+        static ref LINK_REGEX: Regex = Regex::new(
+            r"(?i)(https?://)?([0-9a-z-]+\.)*(bilibili\.com|b23\.tv)/[0-9A-Za-z\-._~:/?#@!$&'()*+,;=%]*"
+        )
+        .unwrap();
+    }
+    let mut links: Vec<Url> = vec![];
+    for m in LINK_REGEX.find_iter(s) {
+        let link = m.as_str();
+        let url = if link.contains("://") {
+            Url::parse(link)
+        } else {
+            Url::parse(&format!("https://{}", link))
+        };
+        match url {
+            Ok(url) if !links.contains(&url) => links.push(url),
+            _ => {}
+        }
+    }
+    links
+}
+
+fn is_bilibili(url: &Url) -> bool {
+    matches!(url.host_str(), Some(host) if host == "bilibili.com" || host.ends_with(".bilibili.com"))
+}
+
+fn is_short_link(url: &Url) -> bool {
+    url.host_str() == Some("b23.tv")
+}
+
+/// 检查视频链接：路径中是实际打开的视频，参数中的视频会被用于预览
+fn inspect(url: &Url) -> Option<Fraud> {
+    if !is_bilibili(url) {
+        return None;
+    }
+    let mut segments = url.path_segments()?;
+    if segments.next() != Some("video") {
+        return None;
+    }
+    let real = Video::parse(segments.next()?)?;
+    let query = url.query().unwrap_or_default();
+    let from_pairs = url
+        .query_pairs()
+        .filter_map(|(key, value)| match key.as_ref() {
+            "aid" | "avid" => Video::from_aid(value.parse().ok()?),
+            _ => None,
+        });
+    let shown = bvid::find_iter(query)
+        .map(|m| m.video)
+        .chain(from_pairs)
+        .find(|video| *video != real)?;
+    Some(Fraud { real, shown })
+}
+
+/// 短链重定向到的地址
+async fn redirect(http: &HttpClient, url: &Url) -> Result<Option<Url>> {
+    let response = http.no_redirect().get(url.clone()).send().await;
+    METRICS.http_response("bilibili", &response);
+    let response = response?;
+    let location = match response.headers().get("location") {
+        Some(location) => location.to_str()?,
+        None => return Ok(None),
+    };
+    Ok(Some(url.join(location)?))
+}
+
+/// 检查链接，短链解析失败时视为正常
+async fn check(http: &HttpClient, mut url: Url) -> Option<Fraud> {
+    for _ in 0..MAX_REDIRECTS {
+        if !is_short_link(&url) {
+            break;
+        }
+        url = match redirect(http, &url).await {
+            Ok(Some(location)) => location,
+            Ok(None) => return None,
+            Err(e) => {
+                warn!("解析短链 {} 失败：{:?}", url, e);
+                return None;
+            }
+        };
+        debug!("短链重定向到 {}", url);
+    }
+    inspect(&url)
+}
+
+/// 视频的 BV 号和标题，获取标题失败时只有 BV 号
+async fn describe(http: &HttpClient, video: Video) -> String {
+    let video_info = biliapi::requests::VideoInfo::request(http.client(), video.bvid()).await;
+    METRICS.api_result("bilibili", &video_info);
+    match video_info {
+        Ok(info) => format!("{}《{}》", video, info.title),
+        Err(e) => {
+            warn!("获取视频 {} 的标题失败：{:?}", video, e);
+            video.to_string()
+        }
+    }
+}
+
+async fn on_message(
+    msg: GroupMessage,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+    http: Data<HttpClient>,
+) -> Result<()> {
+    if !perms.allows(&msg, FraudGuard.name(), Role::Member) {
+        return Ok(());
+    }
+    let mut links = links(&msg.message.to_string());
+    if links.is_empty() {
+        return Ok(());
+    }
+    if !mw.admit(&msg, FraudGuard.name(), &bot).await? {
+        return Ok(());
+    }
+    if links.len() > MAX_LINKS {
+        debug!("消息中有 {} 个链接，只检查前 {} 个", links.len(), MAX_LINKS);
+        links.truncate(MAX_LINKS);
+    }
+    let handler = async {
+        let mut warned = vec![];
+        for url in links {
+            let fraud = match check(&http, url).await {
+                Some(fraud) if !warned.contains(&fraud) => fraud,
+                _ => continue,
+            };
+            warned.push(fraud);
+            info!("发现诈骗链接：预览 {}，实际 {}", fraud.shown, fraud.real);
+            let text = Text::new("fraud_guard.warning")
+                .arg("shown", fraud.shown)
+                .arg("real", describe(&http, fraud.real).await);
+            let config = perms.config().load();
+            let reply = config.messages.for_group(msg.group_id()).get(text);
+            msg.reply(reply, &bot).await?;
+        }
+        Ok(())
+    };
+    mw.run(&msg, &bot, FraudGuard.name(), handler).await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inspect_str(s: &str) -> Option<(u64, u64)> {
+        let url = Url::parse(s).unwrap();
+        inspect(&url).map(|f| (f.real.aid(), f.shown.aid()))
+    }
+
+    #[test]
+    fn test_links() {
+        let links = links(
+            "看 https://www.bilibili.com/video/av170001?BV17b4y1J7ed，还有 b23.tv/abc、https://b23.tv/abc 和 https://example.com/video/av1",
+        );
+        let links = links.iter().map(|u| u.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            links,
+            vec![
+                "https://www.bilibili.com/video/av170001?BV17b4y1J7ed",
+                "https://b23.tv/abc",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inspect() {
+        let fraud = Some((170001, 635700727));
+        assert_eq!(
+            inspect_str("https://www.bilibili.com/video/av170001?BV17b4y1J7ed"),
+            fraud
+        );
+        assert_eq!(
+            inspect_str("https://m.bilibili.com/video/BV17x411w7KC/?bvid=BV17b4y1J7ed"),
+            fraud
+        );
+        assert_eq!(
+            inspect_str("https://www.bilibili.com/video/BV17x411w7KC?aid=635700727"),
+            fraud
+        );
+        // 参数与路径是同一个视频
+        assert_eq!(
+            inspect_str("https://www.bilibili.com/video/av170001?BV17x411w7KC"),
+            None
+        );
+        assert_eq!(
+            inspect_str("https://www.bilibili.com/video/BV17x411w7KC?p=1&share_source=qq"),
+            None
+        );
+        assert_eq!(
+            inspect_str("https://space.bilibili.com/av170001?BV17b4y1J7ed"),
+            None
+        );
+        assert_eq!(
+            inspect_str("https://example.com/video/av170001?BV17b4y1J7ed"),
+            None
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! 帮助，根据注册的插件和命令自动生成
+
+use super::{CommandInfo, Plugin, PLUGINS};
+use crate::i18n::Messages;
+use crate::prelude::*;
+use crate::router;
+use crate::{Middleware, Permissions, Role, Text};
+
+pub struct Help;
+
+const HELP: CommandInfo = CommandInfo {
+    name: "帮助",
+    aliases: &["help"],
+    has_args: true,
+    role: Role::Member,
+    examples: &["帮助", "帮助 封面"],
+};
+
+impl Plugin for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[HELP]
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_help::<GroupMessage>)
+            .handler(on_help::<FriendMessage>);
+    }
+}
+
+/// 一个插件，以及其中当前用户可以使用的命令
+struct Entry {
+    plugin: &'static dyn Plugin,
+    commands: Vec<&'static CommandInfo>,
+}
+
+/// 角色为 `role` 的用户可以使用的插件和命令，`enabled` 判断插件在当前会话中是否开启
+fn entries(role: Role, enabled: impl Fn(&dyn Plugin) -> bool) -> Vec<Entry> {
+    if role == Role::Banned {
+        return vec![];
+    }
+    PLUGINS
+        .iter()
+        .copied()
+        .filter(|plugin| enabled(*plugin))
+        .map(|plugin| Entry {
+            plugin,
+            commands: plugin
+                .commands()
+                .iter()
+                .filter(|c| role >= c.role)
+                .collect(),
+        })
+        .collect()
+}
+
+/// 全部命令的列表，没有命令的插件列在最后
+fn overview(messages: &Messages, entries: &[Entry]) -> String {
+    let mut lines = vec![messages.get("help.overview")];
+    let mut others = vec![];
+    for entry in entries {
+        if entry.plugin.commands().is_empty() {
+            others.push(messages.get(entry.plugin.description()));
+            continue;
+        }
+        if entry.commands.is_empty() {
+            continue;
+        }
+        lines.push(format!("【{}】", messages.get(entry.plugin.description())));
+        for command in entry.commands.iter() {
+            lines.push(format!(
+                "{}：{}",
+                command.name,
+                messages.get(command.help())
+            ));
+        }
+    }
+    if !others.is_empty() {
+        lines.push(messages.get(Text::new("help.others").arg("plugins", others.join("、"))));
+    }
+    lines.push(messages.get(Text::new("help.hint").arg("command", HELP.name)));
+    lines.join("\n")
+}
+
+/// 单个命令的用法
+fn detail(messages: &Messages, command: &CommandInfo) -> String {
+    let mut lines = vec![format!(
+        "【{}】{}",
+        command.name,
+        messages.get(command.help())
+    )];
+    if command.has_args {
+        lines.push(messages.get(Text::new("help.usage").arg_text("usage", command.usage())));
+    }
+    if !command.aliases.is_empty() {
+        let aliases = command.aliases.join("、");
+        lines.push(messages.get(Text::new("help.aliases").arg("aliases", aliases)));
+    }
+    lines.push(messages.get("help.examples"));
+    lines.extend(command.examples.iter().map(|e| e.to_string()));
+    let role = messages.get(command.role.text());
+    lines.push(messages.get(Text::new("help.role").arg("role", role)));
+    lines.join("\n")
+}
+
+/// `帮助` 后面的参数可以是命令，也可以是插件名或者简介
+fn reply(messages: &Messages, entries: &[Entry], arg: &str) -> String {
+    if arg.is_empty() {
+        return overview(messages, entries);
+    }
+    let command = entries
+        .iter()
+        .flat_map(|e| e.commands.iter())
+        .find(|c| c.names().any(|n| n.eq_ignore_ascii_case(arg)));
+    if let Some(command) = command {
+        return detail(messages, command);
+    }
+    let plugin = entries
+        .iter()
+        .position(|e| e.plugin.name() == arg || messages.get(e.plugin.description()) == arg);
+    match plugin {
+        Some(i) => overview(messages, &entries[i..=i]),
+        None => messages.get(
+            Text::new("help.not_found")
+                .arg("name", arg)
+                .arg("command", HELP.name),
+        ),
+    }
+}
+
+async fn on_help<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    let config = perms.config().load();
+    let mut args = match router::route(&config.router, &HELP, msg.as_message()) {
+        Some(args) => args,
+        None => return Ok(()),
+    };
+    if !perms.allows(&msg, Help.name(), HELP.role) {
+        return Ok(());
+    }
+
+    let handler = async {
+        let group = msg.group_id();
+        let entries = entries(perms.role(&msg), |plugin| {
+            config.plugin_enabled_for(perms.account(), plugin.name())
+                && plugin.available(&config, group)
+                && perms.switch().allows(&msg, plugin.name())
+        });
+        let messages = config.messages.for_group(group);
+        let mut text = reply(&messages, &entries, &args.rest());
+        if !config.router.prefixes.is_empty() {
+            let prefixes = config.router.prefixes.join(" ");
+            text.push('\n');
+            text.push_str(&messages.get(Text::new("help.prefixes").arg("prefixes", prefixes)));
+        }
+        msg.reply(text, &bot).await?;
+        Ok(())
+    };
+    mw.run(&msg, &bot, Help.name(), handler).await;
+    Ok(())
+}
+
+#[test]
+fn test_help() {
+    let config = crate::i18n::Config::default();
+    let zh = config.for_group(None);
+    let reply = |entries: &[Entry], arg: &str| reply(&zh, entries, arg);
+    let member = entries(Role::Member, |_| true);
+    let text = reply(&member, "");
+    assert!(text.contains("封面：获取 bilibili 视频封面"));
+    assert!(text.contains("其他功能：关键词回复"));
+    assert!(!text.contains("生成日报"));
+    assert!(!text.contains("reload"));
+
+    let text = reply(&member, "封面");
+    assert!(text.starts_with("【封面】"));
+    assert!(text.contains("封面 BV1AR4y147gy"));
+    assert!(text.ends_with("需要权限：普通"));
+    assert!(text.contains("用法：封面 视频..."));
+    assert!(reply(&member, "KPI").starts_with("【kpi】"));
+    let text = reply(&member, "？");
+    assert!(text.starts_with("【分类】") && text.contains("别名：?、？"));
+    assert!(reply(&member, "生成日报").starts_with("没有找到命令"));
+
+    let text = reply(&member, "schedule");
+    assert!(text.contains("日程表：查看") && !text.contains("封面"));
+    assert!(!text.contains("新日程表"));
+
+    let admin = entries(Role::SuperAdmin, |p| p.name() != "bilibili_cover");
+    let text = reply(&admin, "");
+    assert!(text.contains("生成日报") && text.contains("reload"));
+    assert!(!text.contains("封面："));
+
+    assert!(entries(Role::Banned, |_| true).is_empty());
+
+    let config = crate::i18n::Config::with_locale("en");
+    let en = config.for_group(None);
+    let text = self::reply(&en, &member, "封面");
+    assert!(text.starts_with("【封面】Get bilibili video covers"));
+    assert!(text.contains("Usage: 封面 <video>...") && text.ends_with("Requires: member"));
+    let text = self::reply(&en, &member, "");
+    assert!(text.contains("【Bilibili video covers】") && text.contains("Also: Keyword replies"));
+    assert!(self::reply(&en, &member, "Bilibili video covers").contains("封面：Get"));
+
+    // 每个插件和命令在目录中都有简介、说明和参数格式
+    for plugin in PLUGINS {
+        assert_ne!(
+            zh.get(plugin.description()),
+            format!("plugin.{}", plugin.name())
+        );
+        for command in plugin.commands() {
+            assert!(
+                !zh.get(command.help()).starts_with("help.cmd."),
+                "{}",
+                command.name
+            );
+            if let Some(args) = command.args() {
+                assert!(!zh.get(args).starts_with("help.args."), "{}", command.name);
+            }
+        }
+    }
+}
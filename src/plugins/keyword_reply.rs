@@ -1,16 +1,20 @@
 //! 关键字回复
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 
-use crate::{prelude::*, Config};
+use super::Plugin;
+use crate::{prelude::*, Middleware, Permissions, Role, SharedConfig};
 
+use lazy_static::lazy_static;
 use rand::prelude::*;
-use serde::Deserialize;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
 fn default_max_alias_times() -> u32 {
     3
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct KeywordReplyConfig {
     /// 全文匹配
     #[serde(default)]
@@ -60,22 +64,135 @@ impl KeywordReplyConfig {
         }
         None
     }
+
+    /// 检查配置，返回发现的问题
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for cycle in self.alias_cycles() {
+            problems.push(format!(
+                "keyword_reply.alias 存在循环：{}",
+                cycle.join(" -> ")
+            ));
+        }
+
+        let templates = self
+            .full_match
+            .iter()
+            .map(|(k, v)| ("full_match", k, v))
+            .chain(
+                self.random
+                    .iter()
+                    .flat_map(|(k, vs)| vs.iter().map(move |v| ("random", k, v))),
+            )
+            .chain(self.contain.iter().map(|(k, v)| ("contain", k, v)));
+        for (table, key, xml) in templates {
+            if let Err(e) = check_template(xml) {
+                problems.push(format!("keyword_reply.{}.{} 模板有误：{}", table, key, e));
+            }
+        }
+        problems.sort();
+        problems
+    }
+
+    /// 别名中的循环，每个循环从最小的关键词开始
+    fn alias_cycles(&self) -> Vec<Vec<String>> {
+        let mut cycles = BTreeSet::new();
+        for start in self.alias.keys() {
+            let mut path: Vec<&str> = vec![];
+            let mut current = start.as_str();
+            loop {
+                if let Some(i) = path.iter().position(|s| *s == current) {
+                    let mut cycle = path[i..].iter().map(|s| s.to_string()).collect::<Vec<_>>();
+                    let min = cycle
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, s)| *s)
+                        .map(|(i, _)| i)
+                        .unwrap_or(0);
+                    cycle.rotate_left(min);
+                    cycles.insert(cycle);
+                    break;
+                }
+                // 全文匹配和随机回复先于别名生效，不会继续跳转
+                if self.full_match.contains_key(current) || self.random.contains_key(current) {
+                    break;
+                }
+                match self.alias.get(current) {
+                    Some(next) => {
+                        path.push(current);
+                        current = next;
+                    }
+                    None => break,
+                }
+            }
+        }
+        cycles.into_iter().collect()
+    }
+}
+
+/// 检查 `MessageChain::from_xml` 模板中的标签是否配对
+fn check_template(xml: &str) -> Result<(), String> {
+    lazy_static! {
+        static ref TAG: Regex = Regex::new(r"<\s*(/?)\s*(\w+)[^<>]*?(/?)\s*>").unwrap();
+    }
+    let mut stack = vec![];
+    for cap in TAG.captures_iter(xml) {
+        let name = cap.get(2).map(|m| m.as_str()).unwrap_or_default();
+        // 自闭合标签
+        if !cap[3].is_empty() {
+            continue;
+        }
+        if cap[1].is_empty() {
+            stack.push(name);
+            continue;
+        }
+        match stack.pop() {
+            Some(open) if open == name => {}
+            Some(open) => return Err(format!("<{}> 与 </{}> 不匹配", open, name)),
+            None => return Err(format!("多余的 </{}>", name)),
+        }
+    }
+    match stack.pop() {
+        Some(open) => Err(format!("<{}> 没有闭合", open)),
+        None => Ok(()),
+    }
 }
 
-pub fn init(bot: Bot) {
-    bot.handler(on_msg::<GroupMessage>)
-        .handler(on_msg::<FriendMessage>);
+pub struct KeywordReply;
+
+impl Plugin for KeywordReply {
+    fn name(&self) -> &'static str {
+        "keyword_reply"
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_msg::<GroupMessage>)
+            .handler(on_msg::<FriendMessage>);
+    }
 }
 
 /// 关键字回复
-async fn on_msg<T: Conversation>(msg: T, bot: Bot, config: Data<Config>) -> Result<()> {
+async fn on_msg<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    config: Data<SharedConfig>,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    if !perms.allows(&msg, KeywordReply.name(), Role::Member) {
+        return Ok(());
+    }
     let message = msg.as_message().to_string();
-    let reply = config.keyword_reply.reply(&message);
+    let reply = config.load().keyword_reply.reply(&message);
 
     if let Some(reply) = reply {
         debug!("回复 {:?}", reply);
-        msg.reply_unquote(reply, &bot).await?;
-        info!("关键词回复成功");
+        let handler = async {
+            msg.reply_unquote(reply, &bot).await?;
+            info!("关键词回复成功");
+            Ok(())
+        };
+        mw.run(&msg, &bot, KeywordReply.name(), handler).await;
     }
 
     Ok(())
@@ -100,3 +217,35 @@ alias:
     assert_eq!(cfg.reply("c").unwrap(), MessageBlock::text("1").into());
     assert_eq!(cfg.reply("d").unwrap(), MessageBlock::text("1").into());
 }
+
+#[test]
+fn test_check() {
+    let cfg: KeywordReplyConfig = serde_yaml::from_str(
+        r"
+full_match:
+    a: <i> a.jpg </i>
+    b: <v> b.silk
+contain:
+    c: </i>
+alias:
+    x: y
+    y: z
+    z: x
+    p: a
+    a: p
+    q: q
+",
+    )
+    .unwrap();
+    assert_eq!(
+        cfg.check(),
+        vec![
+            "keyword_reply.alias 存在循环：q",
+            "keyword_reply.alias 存在循环：x -> y -> z",
+            "keyword_reply.contain.c 模板有误：多余的 </i>",
+            "keyword_reply.full_match.b 模板有误：<v> 没有闭合",
+        ]
+    );
+
+    assert!(serde_yaml::from_str::<KeywordReplyConfig>("unknown: 1").is_err());
+}
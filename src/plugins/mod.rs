@@ -1,8 +1,134 @@
+//! 插件
+//!
+//! 每个插件实现 [`Plugin`]，并在 [`PLUGINS`] 中注册。启动时按照 `config.yaml` 中的
+//! `plugins` 一节决定是否启用，未列出的插件默认启用。命令由 [`CommandInfo`] 声明，
+//! 通过 [`crate::router`] 匹配并解析参数。插件简介、命令说明和参数格式在目录中，
+//! 键分别为 `plugin.<插件名>`、`help.cmd.<命令名>` 和 `help.args.<命令名>`。
+
+use crate::i18n::Messages;
+use crate::prelude::*;
+use crate::{Config, Role, Text};
+
 pub mod asoul_cnki;
 pub mod asoul_weekly;
 pub mod bilibili_cover;
 pub mod core;
+pub mod fraud;
+pub mod fraud_guard;
+pub mod help;
 pub mod keyword_reply;
 pub mod schedule;
 pub mod shab;
-pub mod fraud;
+
+/// 插件提供的命令，也用于生成帮助
+#[derive(Debug)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    /// 别名，与命令名等价
+    pub aliases: &'static [&'static str],
+    /// 是否有参数，有参数时目录中的 `help.args.<命令名>` 为参数格式
+    pub has_args: bool,
+    /// 使用命令需要的角色
+    pub role: Role,
+    /// 用法示例
+    pub examples: &'static [&'static str],
+}
+
+impl CommandInfo {
+    /// 命令名和全部别名
+    pub fn names(&self) -> impl Iterator<Item = &'static str> {
+        std::iter::once(self.name).chain(self.aliases.iter().copied())
+    }
+
+    /// 一句话说明
+    pub fn help(&self) -> Text {
+        Text::new(format!("help.cmd.{}", self.name))
+    }
+
+    /// 参数格式，没有参数时为 `None`
+    pub fn args(&self) -> Option<Text> {
+        self.has_args
+            .then(|| Text::new(format!("help.args.{}", self.name)))
+    }
+
+    /// 命令名加上参数格式，用于“用法：…”提示
+    pub fn usage(&self) -> Text {
+        match self.args() {
+            Some(args) => Text::new("help.command_args")
+                .arg("command", self.name)
+                .arg_text("args", args),
+            None => Text::new("help.command").arg("command", self.name),
+        }
+    }
+}
+
+pub trait Plugin: Send + Sync {
+    /// 插件名，即 `config.yaml` 中 `plugins` 下的键
+    fn name(&self) -> &'static str;
+
+    /// 一句话介绍，即目录中的 `plugin.<插件名>`
+    fn description(&self) -> Text {
+        Text::new(format!("plugin.{}", self.name()))
+    }
+
+    /// 插件提供的命令
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[]
+    }
+
+    /// 在群 `group` 中（私聊时为 `None`）能否使用，用于生成帮助。插件开关由 [`Permissions`] 检查
+    ///
+    /// [`Permissions`]: crate::Permissions
+    fn available(&self, _config: &Config, _group: Option<QQ>) -> bool {
+        true
+    }
+
+    /// 注册 handler
+    fn init(&self, bot: Bot);
+}
+
+/// 全部插件
+pub static PLUGINS: &[&dyn Plugin] = &[
+    &core::Core,
+    &asoul_cnki::AsoulCnki,
+    &keyword_reply::KeywordReply,
+    &bilibili_cover::BilibiliCover,
+    &asoul_weekly::AsoulWeekly,
+    &schedule::Schedule,
+    &shab::Shab,
+    &fraud::Fraud,
+    &fraud_guard::FraudGuard,
+    &help::Help,
+];
+
+/// 按名字查找插件
+pub fn find(name: &str) -> Option<&'static dyn Plugin> {
+    PLUGINS.iter().copied().find(|p| p.name() == name)
+}
+
+/// 按插件名、简介或命令查找插件，用于 `开启`/`关闭` 指令。简介使用 `messages` 中的文本
+pub fn lookup(messages: &Messages, s: &str) -> Option<&'static dyn Plugin> {
+    PLUGINS.iter().copied().find(|p| {
+        p.name() == s
+            || messages.get(p.description()) == s
+            || p.commands().iter().any(|c| c.names().any(|n| n == s))
+    })
+}
+
+/// 为账号 `account` 初始化所有启用的插件
+pub fn init(bot: Bot, config: &Config, account: QQ) {
+    for name in config.plugins.keys() {
+        if find(name).is_none() {
+            warn!("配置中的插件 {} 不存在", name);
+        }
+    }
+
+    for plugin in PLUGINS {
+        if config.plugin_enabled_for(account, plugin.name()) {
+            info!("启用插件 {}（{}）", plugin.name(), plugin.description());
+            plugin.init(bot.clone());
+        } else {
+            info!("插件 {} 已禁用", plugin.name());
+        }
+    }
+}
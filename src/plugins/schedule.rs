@@ -1,71 +1,162 @@
 //! 日程表
 //!
 
-use crate::Config;
+use super::{CommandInfo, Plugin};
+use crate::router;
+use crate::store::{Key, Store};
+use crate::{ext::ConversationExt, Middleware, Permissions, Role};
 use anyhow::Result;
 use futures::StreamExt;
 use miraie::prelude::*;
 
-static KEY: &str = "A-SOUL_SCHEDULE_URL";
+/// 旧版本直接保存在默认树中的键
+static LEGACY_KEY: &str = "A-SOUL_SCHEDULE_URL";
+const URL: Key<String> = Key::new("url");
 
-pub fn init(bot: Bot) {
-    bot.command("日程表", on_日程表::<GroupMessage>)
-        .command("日程表", on_日程表::<FriendMessage>)
-        .command("新日程表", on_新日程表::<GroupMessage>)
-        .command("新日程表", on_新日程表::<FriendMessage>);
+pub struct Schedule;
+
+const SCHEDULE: CommandInfo = CommandInfo {
+    name: "日程表",
+    aliases: &[],
+    has_args: false,
+    role: Role::Member,
+    examples: &["日程表"],
+};
+const NEW_SCHEDULE: CommandInfo = CommandInfo {
+    name: "新日程表",
+    aliases: &[],
+    has_args: false,
+    role: Role::Trusted,
+    examples: &["新日程表"],
+};
+
+impl Plugin for Schedule {
+    fn name(&self) -> &'static str {
+        "schedule"
+    }
+
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[SCHEDULE, NEW_SCHEDULE]
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_日程表::<GroupMessage>)
+            .handler(on_日程表::<FriendMessage>)
+            .handler(on_新日程表::<GroupMessage>)
+            .handler(on_新日程表::<FriendMessage>);
+    }
 }
 
-fn get_url(db_path: &str) -> Result<Option<String>> {
-    let db = sled::open(db_path)?;
-    let v = db.get(KEY)?;
-    Ok(v.map(|b| String::from_utf8_lossy(&b).to_string()))
+fn get_url(store: &Store) -> Result<Option<String>> {
+    store.namespace(Schedule.name())?.get(&URL)
 }
 
-fn set_url(db_path: &str, url: &str) -> Result<()> {
-    let db = sled::open(db_path)?;
-    db.insert(KEY, url)?;
-    db.flush()?;
+/// 迁移：旧版本的链接保存在默认树中，搬到 schedule 命名空间
+/// 旧版本只支持单账号，多账号时由第一个迁移的账号接收
+pub(crate) fn migrate_legacy_url(store: &Store) -> Result<()> {
+    let db = store.db();
+    if let Some(v) = db.get(LEGACY_KEY)? {
+        let url = String::from_utf8_lossy(&v).to_string();
+        store.namespace(Schedule.name())?.set(&URL, &url)?;
+        db.remove(LEGACY_KEY)?;
+        db.flush()?;
+    }
     Ok(())
 }
 
-async fn on_日程表<T: Conversation>(msg: T, bot: Bot, config: Data<Config>) -> Result<()> {
-    match get_url(&config.db_path)? {
-        Some(url) => {
-            msg.reply(MessageBlock::image_url(url), &bot).await?;
-        }
-        None => {
-            msg.reply("日程表图片还未设置，使用【新日程表】指令设置", &bot)
-                .await?;
-        }
+fn set_url(store: &Store, url: &str) -> Result<()> {
+    store
+        .namespace(Schedule.name())?
+        .set(&URL, &url.to_string())
+}
+
+async fn on_日程表<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    store: Data<Store>,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    if router::route(&perms.config().load().router, &SCHEDULE, msg.as_message()).is_none() {
+        return Ok(());
+    }
+    if !perms.allows(&msg, Schedule.name(), SCHEDULE.role) {
+        return Ok(());
     }
+    let handler = async {
+        match get_url(&store)? {
+            Some(url) => {
+                msg.reply(MessageBlock::image_url(url), &bot).await?;
+            }
+            None => {
+                let config = perms.config().load();
+                let reply = config
+                    .messages
+                    .for_group(msg.group_id())
+                    .get("schedule.unset");
+                msg.reply(reply, &bot).await?;
+            }
+        }
+        Ok(())
+    };
+    mw.run(&msg, &bot, Schedule.name(), handler).await;
     Ok(())
 }
 
-async fn on_新日程表<T: Conversation>(msg: T, bot: Bot, config: Data<Config>) -> Result<()> {
-    let db_path = config.as_ref().db_path.clone();
-    msg.reply("在群里发送图片以设置新的日程表", &bot).await?;
-    let next_msg = match msg.followed_sender_messages(&bot).next().await {
-        Some(n) => n,
-        None => return Ok(()),
-    };
-    let next_block = match next_msg.as_message().0.last() {
-        Some(i) => i,
-        None => return Ok(()),
-    };
-    info!("新日程表: {:?}", next_block);
-    match next_block {
-        MessageBlock::Image {
-            image_id,
-            url,
-            base64,
-        } => {
-            info!("image: {}, {}, {:?}", image_id, url, base64);
-            set_url(&db_path, url)?;
-            let reply = MessageChain::new().text("日程表已经设置为").image_url(url);
-            next_msg.reply(reply, &bot).await?;
-        }
-        _ => return Ok(()),
+async fn on_新日程表<T: Conversation + ConversationExt + Sync>(
+    msg: T,
+    bot: Bot,
+    store: Data<Store>,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
+    if router::route(
+        &perms.config().load().router,
+        &NEW_SCHEDULE,
+        msg.as_message(),
+    )
+    .is_none()
+    {
+        return Ok(());
     }
+    if !perms.allows(&msg, Schedule.name(), NEW_SCHEDULE.role) {
+        return Ok(());
+    }
+    let handler = async {
+        let config = perms.config().load();
+        let messages = config.messages.for_group(msg.group_id());
+        msg.reply(messages.get("schedule.prompt"), &bot).await?;
+        let next_msg = match msg.followed_sender_messages(&bot).next().await {
+            Some(n) => n,
+            None => return Ok(()),
+        };
+        let next_block = match next_msg.as_message().0.last() {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        info!("新日程表: {:?}", next_block);
+        match next_block {
+            MessageBlock::Image {
+                image_id,
+                url,
+                base64,
+            } => {
+                info!("image: {}, {}, {:?}", image_id, url, base64);
+                let result = set_url(&store, url);
+                let summary = result.as_ref().map(|_| url);
+                mw.audit()
+                    .record(&msg, Schedule.name(), NEW_SCHEDULE.name, &summary);
+                result?;
+                let reply = MessageChain::new()
+                    .text(messages.get("schedule.updated"))
+                    .image_url(url);
+                next_msg.reply(reply, &bot).await?;
+            }
+            _ => return Ok(()),
+        }
+        Ok(())
+    };
+    mw.run(&msg, &bot, Schedule.name(), handler).await;
     Ok(())
 }
 
@@ -74,15 +165,32 @@ fn test_sled_save_load() -> Result<()> {
     pretty_env_logger::try_init().ok();
     let dir = tempfile::tempdir()?;
 
-    let path = dir.path().as_os_str().to_str().unwrap();
+    let db = sled::open(dir.path())?;
+    let store = Store::new(db);
 
-    assert_eq!(get_url(&path)?, None);
+    assert_eq!(get_url(&store)?, None);
 
-    set_url(&path, "HELLO_WORLD")?;
-    assert_eq!(get_url(&path)?, Some("HELLO_WORLD".to_string()));
+    set_url(&store, "HELLO_WORLD")?;
+    assert_eq!(get_url(&store)?, Some("HELLO_WORLD".to_string()));
 
-    set_url(&path, "向晚大魔王")?;
-    assert_eq!(get_url(&path)?, Some("向晚大魔王".to_string()));
+    set_url(&store, "向晚大魔王")?;
+    assert_eq!(get_url(&store)?, Some("向晚大魔王".to_string()));
+
+    Ok(())
+}
+
+#[test]
+fn test_migrate_legacy_url() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let db = sled::open(dir.path())?;
+    db.insert(LEGACY_KEY, "https://example.com/old.jpg")?;
+    let store = Store::new(db);
+    crate::migrate::run(&store, crate::migrate::MIGRATIONS)?;
 
+    assert_eq!(
+        get_url(&store)?,
+        Some("https://example.com/old.jpg".to_string())
+    );
+    assert_eq!(store.db().get(LEGACY_KEY)?, None);
     Ok(())
 }
@@ -1,11 +1,45 @@
+//! 引用回复骂人
+
+use super::{CommandInfo, Plugin};
 use crate::prelude::*;
-use crate::Config;
+use crate::router;
+use crate::{Config, Middleware, Permissions, Role, SharedConfig};
+
+pub struct Shab;
+
+const SHAB: CommandInfo = CommandInfo {
+    name: "啥b",
+    aliases: &["shabi", "shab"],
+    has_args: false,
+    role: Role::Member,
+    examples: &["[引用消息] 啥b"],
+};
+
+impl Plugin for Shab {
+    fn name(&self) -> &'static str {
+        "shab"
+    }
 
-pub fn init(bot: Bot) {
-    bot.handler(on_shabi);
+    fn commands(&self) -> &'static [CommandInfo] {
+        &[SHAB]
+    }
+
+    fn available(&self, _config: &Config, group: Option<QQ>) -> bool {
+        group.is_some()
+    }
+
+    fn init(&self, bot: Bot) {
+        bot.handler(on_shabi);
+    }
 }
 
-async fn on_shabi(msg: GroupMessage, bot: Bot, config: Data<Config>) -> Result<()> {
+async fn on_shabi(
+    msg: GroupMessage,
+    bot: Bot,
+    config: Data<SharedConfig>,
+    perms: Data<Permissions>,
+    mw: Data<Middleware>,
+) -> Result<()> {
     let message = &msg.message;
     // get source
     let source = message
@@ -23,31 +57,27 @@ async fn on_shabi(msg: GroupMessage, bot: Bot, config: Data<Config>) -> Result<(
             return Ok(());
         }
     };
-    if config.is_admin(source) {
+    if config.load().is_admin(source) {
         debug!("不准骂我");
         return Ok(());
     }
 
-    // get text
-    let s = message
-        .0
-        .iter()
-        .filter_map(|s| match s {
-            MessageBlock::Text { text } => Some(text.to_string()),
-            _ => None,
-        })
-        .collect::<Vec<_>>()
-        .join("")
-        .trim()
-        .to_lowercase();
-
-    if matches!(s.as_str(), "啥b" | "shabi" | "shab") {
+    if router::route(&config.load().router, &SHAB, message).is_some() {
+        if !perms.allows(&msg, Shab.name(), SHAB.role) {
+            return Ok(());
+        }
+        if !mw.admit(&msg, Shab.name(), &bot).await? {
+            return Ok(());
+        }
         debug!("准备");
-        msg.reply_unquote(MessageChain::new().at(source), &bot)
-            .await?;
-
-        msg.reply_unquote(MessageChain::from_xml("<v> shab.silk </v>"), &bot)
-            .await?;
+        let handler = async {
+            msg.reply_unquote(MessageChain::new().at(source), &bot)
+                .await?;
+            msg.reply_unquote(MessageChain::from_xml("<v> shab.silk </v>"), &bot)
+                .await?;
+            Ok(())
+        };
+        mw.run(&msg, &bot, Shab.name(), handler).await;
     }
 
     Ok(())
@@ -0,0 +1,268 @@
+//! 命令冷却
+//!
+//! 按（插件，群，用户）使用令牌桶限流，规则在 `config.yaml` 的 `rate_limit` 中按插件名配置，
+//! 没有配置的插件不限流。超级管理员和群管理不受限制。
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::prelude::*;
+use crate::{Role, SharedConfig, Text};
+
+/// 桶的数量超过这个值时清理长时间没有触发的桶
+const MAX_BUCKETS: usize = 4096;
+/// 超过这么久没有触发的桶会被清理
+const BUCKET_TTL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// 桶的容量，即冷却完成后可以连续触发的次数
+    pub capacity: u32,
+    /// 每隔多少秒恢复一次
+    pub refill_seconds: f64,
+    /// 被限流时回复“冷却中 N 秒”，否则静默忽略
+    #[serde(default)]
+    pub reply: bool,
+}
+
+impl Rule {
+    /// 检查规则，返回发现的问题
+    pub fn check(&self, plugin: &str) -> Vec<String> {
+        let mut problems = vec![];
+        if self.capacity == 0 {
+            problems.push(format!("rate_limit.{}.capacity 需要大于 0", plugin));
+        }
+        if self.refill_seconds <= 0.0 || self.refill_seconds.is_nan() {
+            problems.push(format!("rate_limit.{}.refill_seconds 需要大于 0", plugin));
+        }
+        problems
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(rule: &Rule, now: Instant) -> Self {
+        Self {
+            tokens: rule.capacity as f64,
+            updated: now,
+        }
+    }
+
+    /// 取一个令牌，不够时返回还需要等待的时间
+    fn take(&mut self, rule: &Rule, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed / rule.refill_seconds).min(rule.capacity as f64);
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - self.tokens) * rule.refill_seconds;
+            Err(Duration::from_secs_f64(wait.max(0.0)))
+        }
+    }
+}
+
+/// 被限流后的处理方式
+#[derive(Debug, PartialEq)]
+pub enum Limited {
+    /// 静默忽略
+    Drop,
+    /// 回复冷却时间
+    Reply(Text),
+}
+
+type Key = (&'static str, Option<QQ>, QQ);
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<Key, Bucket>>>,
+    config: SharedConfig,
+}
+
+impl RateLimiter {
+    pub fn new(config: SharedConfig) -> Self {
+        Self {
+            buckets: Default::default(),
+            config,
+        }
+    }
+
+    fn check_at(
+        &self,
+        plugin: &'static str,
+        group: Option<QQ>,
+        qq: QQ,
+        role: Role,
+        now: Instant,
+    ) -> Result<(), Limited> {
+        if role >= Role::GroupAdmin {
+            return Ok(());
+        }
+        let config = self.config.load();
+        let rule = match config.rate_limit.get(plugin) {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+
+        let mut buckets = self.buckets.lock();
+        if buckets.len() > MAX_BUCKETS {
+            buckets.retain(|_, b| now.saturating_duration_since(b.updated) < BUCKET_TTL);
+        }
+        let bucket = buckets
+            .entry((plugin, group, qq))
+            .or_insert_with(|| Bucket::full(rule, now));
+        match bucket.take(rule, now) {
+            Ok(()) => Ok(()),
+            Err(wait) => {
+                debug!(
+                    "{} 在群 {:?} 触发 {} 过于频繁，还需等待 {:?}",
+                    qq.0, group, plugin, wait
+                );
+                if rule.reply {
+                    let seconds = wait.as_secs_f64().ceil() as u64;
+                    Err(Limited::Reply(
+                        Text::new("rate_limit.cooling").arg("seconds", seconds),
+                    ))
+                } else {
+                    Err(Limited::Drop)
+                }
+            }
+        }
+    }
+
+    /// 角色为 `role` 的消息发送者能否触发插件 `plugin`，每次调用消耗一次次数
+    pub fn check<T: Conversation + ConversationExt>(
+        &self,
+        msg: &T,
+        plugin: &'static str,
+        role: Role,
+    ) -> Result<(), Limited> {
+        self.check_at(
+            plugin,
+            msg.group_id(),
+            *msg.sender().as_ref(),
+            role,
+            Instant::now(),
+        )
+    }
+
+    /// 同 [`RateLimiter::check`]，被限流时按照配置回复。返回是否可以继续处理
+    pub async fn admit<T: Conversation + ConversationExt + Sync>(
+        &self,
+        msg: &T,
+        plugin: &'static str,
+        role: Role,
+        bot: &Bot,
+    ) -> Result<bool> {
+        match self.check(msg, plugin, role) {
+            Ok(()) => Ok(true),
+            Err(Limited::Drop) => Ok(false),
+            Err(Limited::Reply(text)) => {
+                let reply = self
+                    .config
+                    .load()
+                    .messages
+                    .for_group(msg.group_id())
+                    .get(text);
+                msg.reply(reply, bot).await?;
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(rate_limit: &str) -> RateLimiter {
+        let mut config = crate::Config::for_test();
+        config.rate_limit = serde_yaml::from_str(rate_limit).unwrap();
+        RateLimiter::new(SharedConfig::new(config))
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = limiter(
+            r"
+    fraud:
+        capacity: 2
+        refill_seconds: 10
+        reply: true
+    shab:
+        capacity: 1
+        refill_seconds: 60
+",
+        );
+        let now = Instant::now();
+        let group = Some(QQ(1));
+        let check = |plugin, qq, secs| {
+            let at = now + Duration::from_secs(secs);
+            limiter.check_at(plugin, group, QQ(qq), Role::Member, at)
+        };
+
+        assert_eq!(check("fraud", 200, 0), Ok(()));
+        assert_eq!(check("fraud", 200, 0), Ok(()));
+        assert_eq!(
+            check("fraud", 200, 1),
+            Err(Limited::Reply(
+                Text::new("rate_limit.cooling").arg("seconds", 9)
+            ))
+        );
+        // 其他用户、其他群、其他插件互不影响
+        assert_eq!(check("fraud", 300, 1), Ok(()));
+        assert_eq!(
+            limiter.check_at("fraud", Some(QQ(2)), QQ(200), Role::Member, now),
+            Ok(())
+        );
+        assert_eq!(check("bilibili_cover", 200, 1), Ok(()));
+        // 恢复一次
+        assert_eq!(check("fraud", 200, 11), Ok(()));
+        assert!(check("fraud", 200, 11).is_err());
+
+        assert_eq!(check("shab", 200, 0), Ok(()));
+        assert_eq!(check("shab", 200, 30), Err(Limited::Drop));
+
+        // 超级管理员和群管理不受限制，群内授予的其他角色照常限流
+        for _ in 0..10 {
+            for role in [Role::GroupAdmin, Role::SuperAdmin] {
+                assert_eq!(limiter.check_at("shab", group, QQ(400), role, now), Ok(()));
+            }
+        }
+        assert_eq!(
+            limiter.check_at("shab", group, QQ(400), Role::Trusted, now),
+            Ok(())
+        );
+        assert_eq!(
+            limiter.check_at("shab", group, QQ(400), Role::Trusted, now),
+            Err(Limited::Drop)
+        );
+    }
+
+    #[test]
+    fn test_rule_check() {
+        let rule = Rule {
+            capacity: 0,
+            refill_seconds: 0.0,
+            reply: false,
+        };
+        assert_eq!(rule.check("fraud").len(), 2);
+        for refill_seconds in [-1.0, f64::NAN] {
+            let rule = Rule {
+                capacity: 1,
+                refill_seconds,
+                reply: false,
+            };
+            assert_eq!(rule.check("fraud").len(), 1);
+        }
+    }
+}
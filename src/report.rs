@@ -0,0 +1,246 @@
+//! 错误上报
+//!
+//! handler 出错时给用户回复简短的提示，并把完整的错误链、插件名和消息内容私聊发给管理员。
+//! 相同的错误在 `error_report.dedup_seconds` 秒内只通知一次。
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::i18n::{Messages, Text};
+use crate::prelude::*;
+use crate::trace::Span;
+use crate::SharedConfig;
+
+/// 出现未知错误时给用户的回复
+const FRIENDLY_MESSAGE: &str = "error.internal";
+/// 记录的错误超过这个数量时清理过期的记录
+const MAX_RECENT: usize = 1024;
+
+fn default_dedup_seconds() -> u64 {
+    600
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 相同的错误在这么多秒内只通知一次管理员
+    #[serde(default = "default_dedup_seconds")]
+    pub dedup_seconds: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dedup_seconds: default_dedup_seconds(),
+        }
+    }
+}
+
+/// 用户的输入有误，按群的语言回复给用户，不通知管理员
+///
+/// ```ignore
+/// bail!(UserError::new("fraud.no_video"));
+/// ```
+#[derive(Debug)]
+pub struct UserError(pub Text);
+
+impl UserError {
+    pub fn new(text: impl Into<Text>) -> Self {
+        Self(text.into())
+    }
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// 出错的插件和触发的消息
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    pub plugin: &'static str,
+    pub group: Option<QQ>,
+    pub sender: QQ,
+    pub message: String,
+    /// 日志中的 trace id，见 [`Span`]
+    pub trace_id: String,
+}
+
+impl ErrorContext {
+    pub fn of<T: Conversation + ConversationExt>(msg: &T, span: &Span) -> Self {
+        Self {
+            plugin: span.plugin(),
+            group: msg.group_id(),
+            sender: *msg.sender().as_ref(),
+            message: msg.as_message().to_string(),
+            trace_id: span.trace_id().to_string(),
+        }
+    }
+}
+
+/// 错误链中的 [`UserError`]
+fn user_error(e: &anyhow::Error) -> Option<&UserError> {
+    e.chain().find_map(|c| c.downcast_ref::<UserError>())
+}
+
+/// 回复给用户的提示
+pub fn user_message(e: &anyhow::Error, messages: &Messages) -> String {
+    match user_error(e) {
+        Some(user_error) => messages.get(user_error.0.clone()),
+        None => messages.get(FRIENDLY_MESSAGE),
+    }
+}
+
+/// 发给管理员的通知，`suppressed` 为上次通知之后合并掉的相同错误的次数
+fn admin_message(ctx: &ErrorContext, e: &anyhow::Error, suppressed: u32) -> String {
+    let place = match ctx.group {
+        Some(group) => format!("群 {}", group.0),
+        None => "私聊".to_string(),
+    };
+    let mut message = format!(
+        "插件 {} 出错\n{}，发送者 {}\n消息：{}\ntrace id：{}\n错误：{:?}",
+        ctx.plugin, place, ctx.sender.0, ctx.message, ctx.trace_id, e
+    );
+    if suppressed > 0 {
+        message.push_str(&format!(
+            "\n（上次通知后相同的错误又出现了 {} 次）",
+            suppressed
+        ));
+    }
+    message
+}
+
+struct Seen {
+    notified: Instant,
+    suppressed: u32,
+}
+
+#[derive(Clone)]
+pub struct ErrorReporter {
+    config: SharedConfig,
+    recent: Arc<Mutex<HashMap<String, Seen>>>,
+}
+
+impl ErrorReporter {
+    pub fn new(config: SharedConfig) -> Self {
+        Self {
+            config,
+            recent: Default::default(),
+        }
+    }
+
+    /// 是否需要通知管理员，需要时返回之前合并掉的次数
+    fn should_notify(&self, key: String, now: Instant) -> Option<u32> {
+        let window = Duration::from_secs(self.config.load().error_report.dedup_seconds);
+        let mut recent = self.recent.lock();
+        if recent.len() > MAX_RECENT {
+            recent.retain(|_, seen| now.saturating_duration_since(seen.notified) < window);
+        }
+        match recent.get_mut(&key) {
+            Some(seen) if now.saturating_duration_since(seen.notified) < window => {
+                seen.suppressed += 1;
+                None
+            }
+            Some(seen) => {
+                let suppressed = seen.suppressed;
+                *seen = Seen {
+                    notified: now,
+                    suppressed: 0,
+                };
+                Some(suppressed)
+            }
+            None => {
+                recent.insert(
+                    key,
+                    Seen {
+                        notified: now,
+                        suppressed: 0,
+                    },
+                );
+                Some(0)
+            }
+        }
+    }
+
+    /// 记录错误并通知管理员，用户输入有误时只记录
+    pub async fn report(&self, bot: &Bot, ctx: &ErrorContext, e: &anyhow::Error) {
+        if let Some(user_error) = user_error(e) {
+            info!("插件 {} 用户输入有误：{}", ctx.plugin, user_error);
+            return;
+        }
+        error!("插件 {} 处理消息出错：{:?}，{:?}", ctx.plugin, ctx, e);
+        let key = format!("{}/{:#}", ctx.plugin, e);
+        match self.should_notify(key, Instant::now()) {
+            Some(suppressed) => {
+                let config = self.config.load();
+                let message = admin_message(ctx, e, suppressed);
+                crate::admin::notify_admins(bot, &config, message).await;
+            }
+            None => debug!("相同的错误最近已经通知过管理员"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_message() {
+        let config = crate::i18n::Config::with_locale("en");
+        let zh = crate::i18n::Config::default();
+        let (en, zh) = (config.for_group(None), zh.for_group(None));
+
+        let e = anyhow::Error::from(UserError::new("fraud.no_video"));
+        let expected = "没有找到视频，需要 av 号、BV 号或者视频链接";
+        assert_eq!(user_message(&e, &zh), expected);
+        assert_eq!(e.to_string(), expected);
+        let e = e.context("诈骗");
+        assert_eq!(
+            user_message(&e, &en),
+            "No video found, expected an av id, a BV id or a video link"
+        );
+        assert_eq!(
+            user_message(&anyhow!("timeout"), &zh),
+            "出错了，已经通知管理员"
+        );
+    }
+
+    #[test]
+    fn test_admin_message() {
+        let ctx = ErrorContext {
+            plugin: "fraud",
+            group: Some(QQ(1)),
+            sender: QQ(2),
+            message: "诈骗".to_string(),
+            trace_id: "abc".to_string(),
+        };
+        let e = anyhow!("timeout").context("获取视频信息失败");
+        let message = admin_message(&ctx, &e, 3);
+        assert!(message.starts_with("插件 fraud 出错\n群 1，发送者 2\n消息：诈骗\ntrace id：abc\n"));
+        assert!(message.contains("获取视频信息失败") && message.contains("timeout"));
+        assert!(message.ends_with("又出现了 3 次）"));
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut config = crate::Config::for_test();
+        config.error_report.dedup_seconds = 60;
+        let reporter = ErrorReporter::new(SharedConfig::new(config));
+        let now = Instant::now();
+        let at = |secs| now + Duration::from_secs(secs);
+
+        assert_eq!(reporter.should_notify("a".into(), at(0)), Some(0));
+        assert_eq!(reporter.should_notify("a".into(), at(10)), None);
+        assert_eq!(reporter.should_notify("a".into(), at(59)), None);
+        assert_eq!(reporter.should_notify("b".into(), at(10)), Some(0));
+        assert_eq!(reporter.should_notify("a".into(), at(60)), Some(2));
+        assert_eq!(reporter.should_notify("a".into(), at(61)), None);
+    }
+}
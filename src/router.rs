@@ -0,0 +1,289 @@
+//! 命令路由
+//!
+//! 命令由 [`CommandInfo`] 声明名字、别名和参数格式。[`route`] 判断消息是不是某个命令，
+//! 并把命令后面的内容切分为参数：按空白分隔，引号括起来的内容作为一个参数，@ 单独作为一个参数。
+//! 参数不符合格式时返回“用法：…”的 [`UserError`]，由 [`Middleware::run`] 回复给用户。
+//!
+//! [`Middleware::run`]: crate::Middleware::run
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use crate::plugins::CommandInfo;
+use crate::prelude::*;
+use crate::report::UserError;
+use crate::Text;
+
+/// 成对的引号
+const QUOTES: &[(char, char)] = &[('"', '"'), ('“', '”'), ('「', '」')];
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 命令前缀，如 `/`、`#`。配置后命令必须以其中之一开头，为空时不需要前缀
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+}
+
+impl Config {
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = vec![];
+        for prefix in self.prefixes.iter() {
+            if prefix.is_empty() || prefix.contains(char::is_whitespace) {
+                problems.push(format!(
+                    "router.prefixes 中的 {:?} 不能为空或者包含空白",
+                    prefix
+                ));
+            }
+        }
+        problems
+    }
+
+    /// 去掉命令前缀，没有配置前缀时原样返回
+    fn strip_prefix<'a>(&self, s: &'a str) -> Option<&'a str> {
+        if self.prefixes.is_empty() {
+            return Some(s);
+        }
+        self.prefixes
+            .iter()
+            .find_map(|p| s.strip_prefix(p.as_str()))
+    }
+}
+
+/// 一个参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Arg {
+    Text(String),
+    At(QQ),
+}
+
+/// 把消息切分为参数，图片、引用等其他内容会被忽略
+pub fn tokenize(chain: &MessageChain) -> Vec<Arg> {
+    let mut args = vec![];
+    for block in chain.0.iter() {
+        match block {
+            MessageBlock::Text { text } => split_text(text, &mut args),
+            MessageBlock::At { target, .. } => args.push(Arg::At(*target)),
+            _ => {}
+        }
+    }
+    args
+}
+
+fn split_text(text: &str, args: &mut Vec<Arg>) {
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let first = match chars.next() {
+            Some(c) => c,
+            None => return,
+        };
+        let mut arg = String::new();
+        match QUOTES.iter().find(|(open, _)| *open == first) {
+            // 没有闭合的引号一直到结尾
+            Some((_, close)) => arg.extend(chars.by_ref().take_while(|c| c != close)),
+            None => {
+                arg.push(first);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        args.push(Arg::Text(arg));
+    }
+}
+
+/// 消息是命令 `command` 时返回参数。
+///
+/// 命令前面的 @ 会被忽略（引用回复时会自动加上）。命令不带参数时，后面还有内容的消息不算这个命令
+pub fn route(config: &Config, command: &'static CommandInfo, chain: &MessageChain) -> Option<Args> {
+    let mut args: VecDeque<Arg> = tokenize(chain)
+        .into_iter()
+        .skip_while(|arg| matches!(arg, Arg::At(_)))
+        .collect();
+    let name = match args.pop_front()? {
+        Arg::Text(text) => text,
+        Arg::At(_) => return None,
+    };
+    let name = config.strip_prefix(&name)?;
+    let alias = command.names().find(|n| n.eq_ignore_ascii_case(name))?;
+    if !command.has_args && !args.is_empty() {
+        return None;
+    }
+    Some(Args {
+        command,
+        alias,
+        args,
+    })
+}
+
+/// 命令的参数，依次取出
+#[derive(Debug)]
+pub struct Args {
+    command: &'static CommandInfo,
+    alias: &'static str,
+    args: VecDeque<Arg>,
+}
+
+impl Args {
+    /// 消息中使用的命令名或者别名
+    pub fn alias(&self) -> &'static str {
+        self.alias
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// “用法：…”错误
+    pub fn usage_error(&self) -> Error {
+        UserError::new(Text::new("router.usage").arg_text("usage", self.command.usage())).into()
+    }
+
+    /// 下一个参数，需要是文本
+    pub fn text(&mut self) -> Result<String> {
+        self.optional_text().ok_or_else(|| self.usage_error())
+    }
+
+    /// 下一个参数是文本时取出
+    pub fn optional_text(&mut self) -> Option<String> {
+        match self.args.pop_front()? {
+            Arg::Text(text) => Some(text),
+            arg => {
+                self.args.push_front(arg);
+                None
+            }
+        }
+    }
+
+    /// 下一个参数，需要是 @ 或者 QQ 号
+    pub fn target(&mut self) -> Result<QQ> {
+        self.optional_target().ok_or_else(|| self.usage_error())
+    }
+
+    /// 下一个参数是 @ 或者 QQ 号时取出
+    pub fn optional_target(&mut self) -> Option<QQ> {
+        let target = match self.args.front()? {
+            Arg::At(target) => *target,
+            Arg::Text(text) => QQ(text.parse().ok()?),
+        };
+        self.args.pop_front();
+        Some(target)
+    }
+
+    /// 下一个参数，解析为 `T`
+    pub fn parse<T: FromStr>(&mut self) -> Result<T> {
+        let text = self.text()?;
+        text.parse().map_err(|_| self.usage_error())
+    }
+
+    /// 剩下的全部参数，用空格连接
+    pub fn rest(&mut self) -> String {
+        self.args
+            .drain(..)
+            .map(|arg| match arg {
+                Arg::Text(text) => text,
+                Arg::At(target) => format!("@{}", target.0),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// 参数应该已经全部取出
+    pub fn finish(self) -> Result<()> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.usage_error())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Role;
+
+    const SET_ROLE: CommandInfo = CommandInfo {
+        name: "设置权限",
+        aliases: &["权限"],
+        has_args: true,
+        role: Role::Member,
+        examples: &[],
+    };
+    const PING: CommandInfo = CommandInfo {
+        name: "ping",
+        aliases: &[],
+        has_args: false,
+        role: Role::Member,
+        examples: &[],
+    };
+
+    fn config(prefixes: &[&str]) -> Config {
+        Config {
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let chain = MessageChain::new()
+            .text(" 设置权限  “A SOUL”")
+            .at(QQ(123))
+            .text("\"a b\"  c　d \"未闭合");
+        assert_eq!(
+            tokenize(&chain),
+            vec![
+                Arg::Text("设置权限".to_string()),
+                Arg::Text("A SOUL".to_string()),
+                Arg::At(QQ(123)),
+                Arg::Text("a b".to_string()),
+                Arg::Text("c".to_string()),
+                Arg::Text("d".to_string()),
+                Arg::Text("未闭合".to_string()),
+            ]
+        );
+        assert_eq!(tokenize(&MessageChain::new().text("  ")), vec![]);
+    }
+
+    #[test]
+    fn test_route() -> Result<()> {
+        let none = config(&[]);
+        let chain = MessageChain::new().text("权限 ").at(QQ(123)).text(" 信任");
+        let mut args = route(&none, &SET_ROLE, &chain).unwrap();
+        assert_eq!(args.alias(), "权限");
+        assert_eq!(args.target()?, QQ(123));
+        assert_eq!(args.text()?, "信任");
+        args.finish()?;
+
+        let mut args = route(&none, &SET_ROLE, &MessageChain::new().text("设置权限 123")).unwrap();
+        assert_eq!(args.target()?, QQ(123));
+        let e = args.text().unwrap_err();
+        assert_eq!(e.to_string(), "用法：设置权限 @某人 封禁/普通/信任/群管理");
+
+        let mut args = route(&none, &SET_ROLE, &MessageChain::new().text("设置权限 abc")).unwrap();
+        assert!(args.optional_target().is_none());
+        assert!(args.parse::<u64>().is_err());
+        assert!(route(&none, &SET_ROLE, &MessageChain::new().text("设置权限abc")).is_none());
+
+        // 不带参数的命令
+        assert!(route(&none, &PING, &MessageChain::new().text(" PING ")).is_some());
+        assert!(route(&none, &PING, &MessageChain::new().text("ping 一下")).is_none());
+        let quoted = MessageChain::new().at(QQ(1)).text(" ping");
+        assert!(route(&none, &PING, &quoted).is_some());
+
+        // 前缀
+        let prefixed = config(&["/", "#"]);
+        assert!(route(&prefixed, &PING, &MessageChain::new().text("ping")).is_none());
+        assert!(route(&prefixed, &PING, &MessageChain::new().text("/ping")).is_some());
+        assert!(route(&prefixed, &PING, &MessageChain::new().text("#ping")).is_some());
+        assert!(route(&prefixed, &PING, &MessageChain::new().text("!ping")).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_check() {
+        assert!(config(&["/"]).check().is_empty());
+        assert_eq!(config(&["", "/ "]).check().len(), 2);
+    }
+}
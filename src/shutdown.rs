@@ -0,0 +1,148 @@
+//! 优雅退出
+//!
+//! 收到 SIGTERM 或 SIGINT 后不再处理新的命令，等待正在运行的 handler 结束（有超时），
+//! 然后写回数据库并断开与 mirai 的连接。
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use crate::prelude::*;
+
+/// 检查 handler 是否都已结束的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+struct Inner {
+    stopping: AtomicBool,
+    in_flight: AtomicUsize,
+    trigger: watch::Sender<bool>,
+    triggered: watch::Receiver<bool>,
+}
+
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+/// 正在运行的 handler，drop 时计数减一
+pub struct Guard {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.inner.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (trigger, triggered) = watch::channel(false);
+        Self {
+            inner: Arc::new(Inner {
+                stopping: AtomicBool::new(false),
+                in_flight: AtomicUsize::new(0),
+                trigger,
+                triggered,
+            }),
+        }
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.inner.stopping.load(Ordering::SeqCst)
+    }
+
+    /// 开始退出，之后 [`Shutdown::enter`] 都返回 `None`
+    pub fn trigger(&self) {
+        if !self.inner.stopping.swap(true, Ordering::SeqCst) {
+            info!("开始退出，不再处理新的命令");
+            self.inner.trigger.send(true).ok();
+        }
+    }
+
+    /// 等待 [`Shutdown::trigger`] 被调用
+    pub async fn triggered(&self) {
+        let mut rx = self.inner.triggered.clone();
+        while !*rx.borrow() {
+            if rx.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// 开始运行一个 handler，正在退出时返回 `None`
+    pub fn enter(&self) -> Option<Guard> {
+        self.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = Guard {
+            inner: self.inner.clone(),
+        };
+        if self.is_stopping() {
+            return None;
+        }
+        Some(guard)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// 等待正在运行的 handler 全部结束，超时返回 `false`
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        let wait = async {
+            while self.in_flight() > 0 {
+                sleep(POLL_INTERVAL).await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.is_ok()
+    }
+}
+
+/// 等待 SIGTERM 或 SIGINT
+pub async fn signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => info!("收到 SIGTERM"),
+            r = tokio::signal::ctrl_c() => {
+                r?;
+                info!("收到 SIGINT");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await?;
+        info!("收到 Ctrl-C");
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown() {
+    let shutdown = Shutdown::new();
+    assert!(shutdown.wait_idle(Duration::from_millis(10)).await);
+
+    let guard = shutdown.enter().unwrap();
+    assert_eq!(shutdown.in_flight(), 1);
+    shutdown.trigger();
+    shutdown.triggered().await;
+    assert!(shutdown.enter().is_none());
+    assert_eq!(shutdown.in_flight(), 1);
+    assert!(!shutdown.wait_idle(Duration::from_millis(50)).await);
+
+    tokio::spawn(async move {
+        sleep(Duration::from_millis(50)).await;
+        drop(guard);
+    });
+    assert!(shutdown.wait_idle(Duration::from_secs(5)).await);
+    assert_eq!(shutdown.in_flight(), 0);
+}
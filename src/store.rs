@@ -0,0 +1,229 @@
+//! 类型化的键值存储
+//!
+//! 每个插件一个命名空间，对应 sled 中名为 `plugin/<插件名>` 的树，值用 json 序列化。
+//! 多账号时每个账号的树名前面再加上 `account/<QQ>/`。
+//! 键用 [`Key`] 声明，同时确定了值的类型：
+//!
+//! ```ignore
+//! const URL: Key<String> = Key::new("url");
+//!
+//! let ns = store.namespace("schedule")?;
+//! ns.set(&URL, &url)?;
+//! let url: Option<String> = ns.get(&URL)?;
+//! ```
+
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+use crate::prelude::*;
+
+/// 值类型为 `V` 的键
+pub struct Key<V> {
+    name: &'static str,
+    _value: PhantomData<fn() -> V>,
+}
+
+impl<V> Key<V> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            _value: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// 多账号时账号数据所在的树名前缀
+const ACCOUNT_PREFIX: &str = "account/";
+
+/// 不属于任何账号的树
+const SHARED_TREES: &[&str] = &["__sled__default", crate::migrate::SCHEMA_TREE];
+
+/// 整个进程共享的数据库，启动时打开一次；每个账号一个 `Store`，树名带上账号的前缀
+#[derive(Clone)]
+pub struct Store {
+    db: sled::Db,
+    prefix: String,
+}
+
+impl Store {
+    /// 单账号，树名不加前缀
+    pub fn new(db: sled::Db) -> Self {
+        Self {
+            db,
+            prefix: String::new(),
+        }
+    }
+
+    /// 多账号时账号 `qq` 的数据
+    pub fn for_account(db: sled::Db, qq: QQ) -> Self {
+        Self {
+            db,
+            prefix: format!("{}{}/", ACCOUNT_PREFIX, qq.0),
+        }
+    }
+
+    pub fn db(&self) -> &sled::Db {
+        &self.db
+    }
+
+    /// 本账号的树 `name` 在数据库中的完整名字
+    pub fn tree_name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+
+    /// 打开本账号的树 `name`
+    pub fn tree(&self, name: &str) -> Result<sled::Tree> {
+        Ok(self.db.open_tree(self.tree_name(name))?)
+    }
+
+    /// 插件 `plugin` 的命名空间
+    pub fn namespace(&self, plugin: &str) -> Result<Namespace> {
+        let tree = self.tree(&format!("plugin/{}", plugin))?;
+        Ok(Namespace { tree })
+    }
+
+    /// 从单账号改为多账号时，把不带前缀的树连同迁移的版本号移到本账号下，返回移动的树名。
+    /// 本账号下已经有同名的数据时保留原来的树，不覆盖
+    pub fn adopt_legacy(&self) -> Result<Vec<String>> {
+        let mut adopted = vec![];
+        if self.prefix.is_empty() {
+            return Ok(adopted);
+        }
+        for name in self.db.tree_names() {
+            let name = String::from_utf8_lossy(&name).into_owned();
+            if name.starts_with(ACCOUNT_PREFIX) || SHARED_TREES.contains(&name.as_str()) {
+                continue;
+            }
+            let legacy = self.db.open_tree(&name)?;
+            if legacy.is_empty() {
+                continue;
+            }
+            let tree = self.tree(&name)?;
+            if !tree.is_empty() {
+                warn!("账号数据 {} 已经存在，保留原来的树", name);
+                continue;
+            }
+            for kv in legacy.iter() {
+                let (k, v) = kv?;
+                tree.insert(k, v)?;
+            }
+            tree.flush()?;
+            let schema = self.db.open_tree(crate::migrate::SCHEMA_TREE)?;
+            if let Some(version) = schema.remove(&name)? {
+                schema.insert(self.tree_name(&name), version)?;
+                schema.flush()?;
+            }
+            self.db.drop_tree(&name)?;
+            adopted.push(name);
+        }
+        Ok(adopted)
+    }
+}
+
+#[derive(Clone)]
+pub struct Namespace {
+    tree: sled::Tree,
+}
+
+impl Namespace {
+    pub fn get<V: DeserializeOwned>(&self, key: &Key<V>) -> Result<Option<V>> {
+        match self.tree.get(key.name)? {
+            Some(v) => {
+                let value = serde_json::from_slice(&v)
+                    .with_context(|| format!("无法解析 {} 的值", key.name))?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// 写入并落盘
+    pub fn set<V: Serialize>(&self, key: &Key<V>, value: &V) -> Result<()> {
+        self.tree.insert(key.name, serde_json::to_vec(value)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// 删除并返回原来的值
+    pub fn remove<V: DeserializeOwned>(&self, key: &Key<V>) -> Result<Option<V>> {
+        let old = self.get(key)?;
+        self.tree.remove(key.name)?;
+        self.tree.flush()?;
+        Ok(old)
+    }
+}
+
+#[test]
+fn test_store() -> Result<()> {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Counter {
+        name: String,
+        count: u32,
+    }
+    const COUNTER: Key<Counter> = Key::new("counter");
+    const NAME: Key<String> = Key::new("counter");
+
+    let dir = tempfile::tempdir()?;
+    let store = Store::new(sled::open(dir.path())?);
+    let a = store.namespace("a")?;
+    let b = store.namespace("b")?;
+
+    assert_eq!(a.get(&COUNTER)?, None);
+    let counter = Counter {
+        name: "向晚".to_string(),
+        count: 1,
+    };
+    a.set(&COUNTER, &counter)?;
+    assert_eq!(a.get(&COUNTER)?, Some(counter));
+    // 命名空间互不影响
+    assert_eq!(b.get(&COUNTER)?, None);
+    // 类型不匹配时报错而不是返回错误的值
+    assert!(a.get(&NAME).is_err());
+
+    b.set(&NAME, &"嘉然".to_string())?;
+    assert_eq!(b.remove(&NAME)?, Some("嘉然".to_string()));
+    assert_eq!(b.get(&NAME)?, None);
+    Ok(())
+}
+
+#[test]
+fn test_account_store() -> Result<()> {
+    const NAME: Key<String> = Key::new("name");
+
+    let dir = tempfile::tempdir()?;
+    let db = sled::open(dir.path())?;
+    let legacy = Store::new(db.clone());
+    legacy.namespace("a")?.set(&NAME, &"向晚".to_string())?;
+    legacy.tree("permission")?.insert("1/2", "trusted")?;
+    let migrations = [crate::migrate::Migration {
+        tree: "plugin/a",
+        version: 1,
+        description: "test",
+        run: |_| Ok(()),
+    }];
+    crate::migrate::run(&legacy, &migrations)?;
+
+    let first = Store::for_account(db.clone(), QQ(1));
+    let second = Store::for_account(db.clone(), QQ(2));
+    assert_eq!(first.namespace("a")?.get(&NAME)?, None);
+
+    let mut adopted = first.adopt_legacy()?;
+    adopted.sort();
+    assert_eq!(adopted, vec!["permission", "plugin/a"]);
+    assert_eq!(first.namespace("a")?.get(&NAME)?, Some("向晚".to_string()));
+    assert!(first.tree("permission")?.get("1/2")?.is_some());
+    assert_eq!(legacy.namespace("a")?.get(&NAME)?, None);
+    // 迁移的版本号跟着树移动
+    assert_eq!(crate::migrate::version(&legacy, "plugin/a")?, 0);
+    assert_eq!(crate::migrate::version(&first, "plugin/a")?, 1);
+    assert!(crate::migrate::run(&first, &migrations)?.is_empty());
+    // 账号之间互不影响，已经移动过的不会再移动
+    assert_eq!(second.namespace("a")?.get(&NAME)?, None);
+    assert!(second.adopt_legacy()?.is_empty());
+    assert!(legacy.adopt_legacy()?.is_empty());
+    Ok(())
+}
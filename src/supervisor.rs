@@ -0,0 +1,308 @@
+//! 连接守护
+//!
+//! 与 mirai 的连接断开后无限重连，重连间隔指数增长并带有随机抖动。配置、数据库等状态在
+//! [`Supervisor`] 中只创建一次，每次重连后重新挂到新的 [`Bot`] 上。
+//!
+//! 每个账号一个 [`Supervisor`]，共享配置和数据库，各自连接、重连。
+
+use rand::Rng;
+use std::time::Instant;
+
+use crate::prelude::*;
+
+use crate::config::Account;
+use crate::metrics::METRICS;
+use crate::shutdown::Shutdown;
+use crate::watcher::ConfigWatcher;
+use crate::{plugins, ConfigSource, HttpClient, Middleware, Permissions, SharedConfig, Store};
+
+/// 第一次重连的间隔
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// 最长重连间隔
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+fn default_notify_after_minutes() -> u64 {
+    5
+}
+
+fn default_shutdown_timeout_seconds() -> u64 {
+    8
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// 断线超过这么多分钟，恢复后私聊通知管理员
+    #[serde(default = "default_notify_after_minutes")]
+    pub notify_after_minutes: u64,
+    /// 退出时最多等待正在运行的命令这么多秒
+    #[serde(default = "default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            notify_after_minutes: default_notify_after_minutes(),
+            shutdown_timeout_seconds: default_shutdown_timeout_seconds(),
+        }
+    }
+}
+
+/// 第 `attempt` 次重连前等待的时间，`jitter` 为随机系数
+fn backoff(attempt: u32, jitter: f64) -> Duration {
+    let exp = attempt.saturating_sub(1).min(16) as i32;
+    let delay = (INITIAL_BACKOFF.as_secs_f64() * 2f64.powi(exp)).min(MAX_BACKOFF.as_secs_f64());
+    Duration::from_secs_f64(delay * jitter)
+}
+
+pub struct Supervisor {
+    config: SharedConfig,
+    account: Account,
+    /// 第一个账号，负责监听配置文件
+    primary: bool,
+    store: Store,
+    permissions: Permissions,
+    middleware: Middleware,
+    http: HttpClient,
+}
+
+impl Supervisor {
+    /// 读取配置、打开数据库，每个账号返回一个 `Supervisor`
+    pub fn new(source: ConfigSource) -> Result<Vec<Self>> {
+        let config = SharedConfig::load_from(source)?;
+        let db = sled::open(&config.load().db_path).context("打开数据库失败")?;
+        Self::accounts(config, db)
+    }
+
+    /// 使用已经打开的配置和数据库，测试中使用临时数据库
+    pub fn accounts(config: SharedConfig, db: sled::Db) -> Result<Vec<Self>> {
+        let current = config.load();
+        let multiple = !current.accounts.is_empty();
+        let shutdown = Shutdown::new();
+        let http = HttpClient::new(&current.http).context("创建 HTTP 客户端失败")?;
+        let mut supervisors = vec![];
+        for (i, account) in current.accounts().into_iter().enumerate() {
+            let store = if multiple {
+                Store::for_account(db.clone(), account.qq)
+            } else {
+                Store::new(db.clone())
+            };
+            if i == 0 {
+                let adopted = store.adopt_legacy().context("迁移单账号的数据失败")?;
+                if !adopted.is_empty() {
+                    info!("单账号的数据 {:?} 已移到账号 {} 下", adopted, account.qq.0);
+                }
+            }
+            crate::migrate::run(&store, crate::migrate::MIGRATIONS)
+                .with_context(|| format!("账号 {} 的数据库迁移失败", account.qq.0))?;
+            let permissions = Permissions::new(&store, config.clone(), account.qq)?;
+            let middleware = Middleware::new(
+                &store,
+                config.clone(),
+                permissions.clone(),
+                shutdown.clone(),
+            )?;
+            supervisors.push(Self {
+                config: config.clone(),
+                account,
+                primary: i == 0,
+                store,
+                permissions,
+                middleware,
+                http: http.clone(),
+            });
+        }
+        Ok(supervisors)
+    }
+
+    /// 只有一个账号时使用
+    pub fn from_parts(config: SharedConfig, db: sled::Db) -> Result<Self> {
+        Self::accounts(config, db)?
+            .into_iter()
+            .next()
+            .context("没有配置账号")
+    }
+
+    pub fn config(&self) -> &SharedConfig {
+        &self.config
+    }
+
+    pub fn account(&self) -> &Account {
+        &self.account
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        self.middleware.shutdown()
+    }
+
+    /// 挂上共享状态并注册插件
+    pub fn setup(&self, bot: Bot) -> Bot {
+        let bot = bot
+            .bot_data(Data::new(self.permissions.clone()))
+            .bot_data(Data::new(self.middleware.clone()))
+            .bot_data(Data::new(self.store.clone()))
+            .bot_data(Data::new(self.http.clone()))
+            .bot_data(Data::new(self.config.clone()));
+        crate::metrics::init(bot.clone());
+        plugins::init(bot.clone(), &self.config.load(), self.account.qq);
+        bot
+    }
+
+    /// 运行所有账号，收到退出信号后等所有账号断开，再写回数据库
+    pub async fn run_all(supervisors: &[Supervisor]) {
+        let first = match supervisors.first() {
+            Some(first) => first,
+            None => return,
+        };
+        if let Some(listen) = first.config.load().metrics.listen.clone() {
+            tokio::spawn(async move {
+                if let Err(e) = crate::metrics::serve(&listen).await {
+                    error!("监控服务退出：{:?}", e);
+                }
+            });
+        }
+        let shutdown = first.shutdown().clone();
+        tokio::spawn(async move {
+            match crate::shutdown::signal().await {
+                Ok(()) => shutdown.trigger(),
+                Err(e) => error!("监听退出信号失败：{:?}", e),
+            }
+        });
+
+        futures::future::join_all(supervisors.iter().map(|s| s.run())).await;
+
+        match first.store.db().flush_async().await {
+            Ok(bytes) => info!("数据库已写回（{} 字节）", bytes),
+            Err(e) => error!("写回数据库失败：{:?}", e),
+        }
+        info!("已退出");
+    }
+
+    /// 一直运行，断线后重连，退出时返回
+    pub async fn run(&self) {
+        let shutdown = self.shutdown().clone();
+        let account = &self.account;
+        let mut attempt = 0;
+        // 上一次连接断开的时间，启动时的连接失败不算断线
+        let mut disconnected_at: Option<Instant> = None;
+        loop {
+            let config = self.config.load();
+            info!(
+                "账号 {} 连接状态：连接中（第 {} 次尝试）",
+                account.qq.0,
+                attempt + 1
+            );
+            let connect = miraie::Bot::new(&account.addr, &account.verify_key, account.qq);
+            let connected = tokio::select! {
+                connected = connect => connected,
+                _ = shutdown.triggered() => break,
+            };
+            match connected {
+                Ok((bot, con)) => {
+                    info!("账号 {} 连接状态：已连接", account.qq.0);
+                    METRICS.set_connected(account.qq, true);
+                    attempt = 0;
+                    let bot = self.setup(bot);
+                    let _watcher = if self.primary {
+                        match ConfigWatcher::spawn(bot.clone(), self.config.clone()) {
+                            Ok(watcher) => Some(watcher),
+                            Err(e) => {
+                                error!("监听配置文件失败：{:?}", e);
+                                None
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    if let Some(outage) = disconnected_at.take().map(|t| t.elapsed()) {
+                        info!("账号 {} 断线 {} 秒后恢复", account.qq.0, outage.as_secs());
+                        let threshold =
+                            Duration::from_secs(config.supervisor.notify_after_minutes * 60);
+                        if outage >= threshold {
+                            let bot = bot.clone();
+                            let config = config.clone();
+                            let qq = account.qq;
+                            tokio::spawn(async move {
+                                let message = format!(
+                                    "机器人 {} 断线 {} 分钟后已恢复连接",
+                                    qq.0,
+                                    outage.as_secs() / 60
+                                );
+                                crate::admin::notify_admins(&bot, &config, message).await;
+                            });
+                        }
+                    }
+
+                    let mut con = Box::pin(con.run());
+                    let result = tokio::select! {
+                        result = &mut con => Some(result),
+                        _ = shutdown.triggered() => None,
+                    };
+                    let result = match result {
+                        Some(result) => result,
+                        None => {
+                            // 等待期间连接保持运行，正在运行的命令还能回复
+                            let timeout =
+                                Duration::from_secs(config.supervisor.shutdown_timeout_seconds);
+                            drain(&shutdown, timeout, &mut con).await;
+                            // drop 掉连接，关闭与 mirai 的 WebSocket
+                            drop(con);
+                            METRICS.set_connected(account.qq, false);
+                            info!("账号 {} 连接状态：已断开与 mirai 的连接", account.qq.0);
+                            break;
+                        }
+                    };
+                    match result {
+                        Ok(_) => warn!("账号 {} 连接状态：已断开", account.qq.0),
+                        Err(e) => warn!("账号 {} 连接状态：已断开，{:?}", account.qq.0, e),
+                    }
+                    METRICS.set_connected(account.qq, false);
+                    disconnected_at = Some(Instant::now());
+                }
+                Err(e) => {
+                    warn!("账号 {} 连接状态：连接失败，{:?}", account.qq.0, e);
+                }
+            }
+
+            attempt += 1;
+            let delay = backoff(attempt, rand::thread_rng().gen_range(0.5..1.5));
+            info!(
+                "账号 {} 连接状态：等待 {:.1} 秒后重连",
+                account.qq.0,
+                delay.as_secs_f64()
+            );
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = shutdown.triggered() => break,
+            }
+        }
+    }
+}
+
+/// 等待正在运行的命令结束，最多等 `timeout`
+async fn drain<F>(shutdown: &Shutdown, timeout: Duration, con: &mut F)
+where
+    F: std::future::Future + Unpin,
+{
+    info!("等待 {} 个正在运行的命令结束", shutdown.in_flight());
+    tokio::select! {
+        idle = shutdown.wait_idle(timeout) => {
+            if !idle {
+                warn!("等待超时，仍有 {} 个命令未结束", shutdown.in_flight());
+            }
+        }
+        _ = con => warn!("等待命令结束时连接已断开"),
+    }
+}
+
+#[test]
+fn test_backoff() {
+    assert_eq!(backoff(1, 1.0), Duration::from_secs(1));
+    assert_eq!(backoff(2, 1.0), Duration::from_secs(2));
+    assert_eq!(backoff(5, 1.0), Duration::from_secs(16));
+    assert_eq!(backoff(10, 1.0), MAX_BACKOFF);
+    assert_eq!(backoff(u32::MAX, 1.0), MAX_BACKOFF);
+    assert_eq!(backoff(2, 0.5), Duration::from_secs(1));
+}
@@ -0,0 +1,135 @@
+//! 日志追踪
+//!
+//! 每条收到的消息按所在的群、发送者和消息 id 得到一个 trace id，同一条消息触发的各个插件使用同一个 id。
+//! 插件处理消息期间，trace id、群号、发送者和插件名写入 log4rs 的 MDC：pattern encoder 用
+//! `{X(trace_id)}` 输出，json encoder 输出在 `mdc` 字段中，见 `log4rs.yml`。
+//!
+//! MDC 是线程局部的，而 future 可能在不同的线程上运行，所以 [`Span::instrument`] 在每次 poll 时写入、
+//! poll 结束后恢复。
+
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+
+use futures::future::poll_fn;
+
+use crate::prelude::*;
+
+/// 一条消息在某个插件中的处理
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    trace_id: String,
+    group: Option<QQ>,
+    sender: QQ,
+    plugin: &'static str,
+}
+
+impl Span {
+    pub fn of<T: Conversation + ConversationExt>(msg: &T, plugin: &'static str) -> Self {
+        let group = msg.group_id();
+        let sender = *msg.sender().as_ref();
+        let source = msg.as_message().0.iter().find_map(|block| match block {
+            MessageBlock::Source { id, .. } => Some(id),
+            _ => None,
+        });
+        Self {
+            trace_id: trace_id(group, sender, source),
+            group,
+            sender,
+            plugin,
+        }
+    }
+
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    pub fn plugin(&self) -> &'static str {
+        self.plugin
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("trace_id", self.trace_id.clone()),
+            ("sender", self.sender.0.to_string()),
+            ("plugin", self.plugin.to_string()),
+        ];
+        if let Some(group) = self.group {
+            fields.push(("group", group.0.to_string()));
+        }
+        fields
+    }
+
+    /// 运行 `fut`，期间的日志都带上本次处理的字段
+    pub async fn instrument<F: Future>(&self, fut: F) -> F::Output {
+        tokio::pin!(fut);
+        poll_fn(|cx| {
+            let _guard = log_mdc::extend_scoped(self.fields());
+            fut.as_mut().poll(cx)
+        })
+        .await
+    }
+}
+
+/// 同一条消息得到相同的 id；没有消息 id 时无法关联，每次生成新的 id
+fn trace_id(group: Option<QQ>, sender: QQ, source: Option<impl Hash>) -> String {
+    let mut hasher = DefaultHasher::new();
+    group.map(|g| g.0).hash(&mut hasher);
+    sender.0.hash(&mut hasher);
+    match source {
+        Some(id) => id.hash(&mut hasher),
+        None => rand::random::<u64>().hash(&mut hasher),
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mdc(key: &str) -> Option<String> {
+        log_mdc::get(key, |v| v.map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_trace_id() {
+        let id = trace_id(Some(QQ(1)), QQ(2), Some(3));
+        assert_eq!(id.len(), 16);
+        assert_eq!(id, trace_id(Some(QQ(1)), QQ(2), Some(3)));
+        assert_ne!(id, trace_id(Some(QQ(1)), QQ(2), Some(4)));
+        assert_ne!(id, trace_id(None, QQ(2), Some(3)));
+        assert_ne!(
+            trace_id(None, QQ(2), None::<i64>),
+            trace_id(None, QQ(2), None::<i64>)
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_instrument() {
+        let span = Span {
+            trace_id: "abc".to_string(),
+            group: None,
+            sender: QQ(2),
+            plugin: "core",
+        };
+        let fields = span
+            .instrument(async {
+                tokio::task::yield_now().await;
+                // 换了线程也能取到
+                let fields = (mdc("trace_id"), mdc("sender"), mdc("plugin"), mdc("group"));
+                tokio::task::yield_now().await;
+                fields
+            })
+            .await;
+        assert_eq!(
+            fields,
+            (
+                Some("abc".to_string()),
+                Some("2".to_string()),
+                Some("core".to_string()),
+                None
+            )
+        );
+        assert_eq!(mdc("trace_id"), None);
+    }
+}
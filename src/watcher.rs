@@ -0,0 +1,78 @@
+//! 监听配置文件变化并自动 reload
+
+use anyhow::{Context, Result};
+use miraie::prelude::*;
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::describe_error;
+use crate::SharedConfig;
+
+/// 编辑器保存时往往会连续触发多个事件，合并这段时间内的事件
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// drop 之后停止监听
+pub struct ConfigWatcher {
+    _watcher: RecommendedWatcher,
+}
+
+impl ConfigWatcher {
+    pub fn spawn(bot: Bot, config: SharedConfig) -> Result<Self> {
+        let path = config.source().path.clone();
+        let file_name = path.file_name().context("配置文件路径不合法")?.to_owned();
+        // 很多编辑器保存时会先写临时文件再重命名，因此监听所在的目录
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut watcher = notify::watcher(tx, DEBOUNCE)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        info!("开始监听配置文件 {}", path.display());
+
+        let (changed_tx, mut changed_rx) = tokio::sync::mpsc::unbounded_channel();
+        std::thread::spawn(move || {
+            // watcher drop 之后 recv 返回错误，线程退出
+            while let Ok(event) = rx.recv() {
+                let changed = match &event {
+                    DebouncedEvent::Create(p)
+                    | DebouncedEvent::Write(p)
+                    | DebouncedEvent::Rename(_, p) => p.file_name() == Some(file_name.as_os_str()),
+                    _ => false,
+                };
+                if changed && changed_tx.send(()).is_err() {
+                    break;
+                }
+            }
+            debug!("配置文件监听线程退出");
+        });
+
+        tokio::spawn(async move {
+            while changed_rx.recv().await.is_some() {
+                while changed_rx.try_recv().is_ok() {}
+                on_change(&bot, &config).await;
+            }
+        });
+
+        Ok(Self { _watcher: watcher })
+    }
+}
+
+async fn on_change(bot: &Bot, config: &SharedConfig) {
+    info!("配置文件发生变化，自动 reload");
+    match config.reload() {
+        Ok(changes) => {
+            info!("自动 reload 成功，变化：{:?}", changes);
+        }
+        Err(e) => {
+            error!("自动 reload 失败：{:?}", e);
+            let message = format!(
+                "配置文件自动 reload 失败，继续使用原配置：\n{}",
+                describe_error(&e)
+            );
+            crate::admin::notify_admins(bot, &config.load(), message).await;
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! 通过模拟的 mirai-api-http 端到端测试插件，不需要网络
+
+use anyhow::Result;
+use avabot::mock::{at, plain, MockMirai};
+use avabot::supervisor::Supervisor;
+use avabot::{Config, SharedConfig};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Response, Server};
+use std::convert::Infallible;
+use std::time::Duration;
+use tempfile::TempDir;
+
+const GROUP: u64 = 1000;
+const ADMIN: u64 = 100;
+const MEMBER: u64 = 200;
+
+struct Harness {
+    mirai: MockMirai,
+    _db: TempDir,
+}
+
+/// 连接 `mirai` 的配置，数据库放在 `dir`
+fn config(mirai: &MockMirai, dir: &TempDir) -> Result<Config> {
+    let config = serde_yaml::from_str(&format!(
+        r"
+qq: 1
+verify_key: key
+addr: '{}'
+db_path: '{}'
+admins: [{}]
+keyword_reply:
+    full_match:
+        你好: 你也好
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        mirai.addr(),
+        dir.path().display(),
+        ADMIN
+    ))?;
+    Ok(config)
+}
+
+/// 启动模拟的 mirai 并连接机器人
+async fn start() -> Result<Harness> {
+    start_with(|_| {}).await
+}
+
+/// 用 `edit` 修改配置后启动
+async fn start_with(edit: impl FnOnce(&mut Config)) -> Result<Harness> {
+    let _ = pretty_env_logger::try_init();
+    let mirai = MockMirai::start().await?;
+    let dir = tempfile::tempdir()?;
+    let mut config = config(&mirai, &dir)?;
+    edit(&mut config);
+    let db = sled::open(dir.path())?;
+    let supervisor = Supervisor::from_parts(SharedConfig::new(config.clone()), db)?;
+
+    let (bot, con) = miraie::Bot::new(&config.addr, &config.verify_key, config.qq).await?;
+    supervisor.setup(bot);
+    tokio::spawn(con.run());
+    mirai.wait_connected().await?;
+
+    Ok(Harness { mirai, _db: dir })
+}
+
+/// 对任何请求都返回 `status` 和 json `body` 的 HTTP 服务，返回它的地址
+fn backend(status: u16, body: &'static str) -> Result<String> {
+    let make = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |_| async move {
+            Response::builder()
+                .status(status)
+                .header("content-type", "application/json")
+                .body(Body::from(body))
+        }))
+    });
+    let server = Server::try_bind(&([127, 0, 0, 1], 0).into())?.serve(make);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    Ok(url)
+}
+
+#[tokio::test]
+async fn test_keyword_reply() -> Result<()> {
+    let h = start().await?;
+
+    h.mirai.group_text(GROUP, MEMBER, "你好");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.target(), Some(GROUP));
+    assert_eq!(call.text(), "你也好");
+
+    h.mirai.friend_text(MEMBER, "你好");
+    let call = h.mirai.expect_call("sendFriendMessage").await?;
+    assert_eq!(call.target(), Some(MEMBER));
+    assert_eq!(call.text(), "你也好");
+
+    h.mirai.group_text(GROUP, MEMBER, "没有配置的关键词");
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ping_recall() -> Result<()> {
+    let h = start().await?;
+
+    h.mirai.group_text(GROUP, MEMBER, "ping");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "pong");
+
+    h.mirai.expect_call("recall").await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_disable_plugin() -> Result<()> {
+    let h = start().await?;
+
+    // 普通群员没有权限
+    h.mirai.group_text(GROUP, MEMBER, "关闭 关键词回复");
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+
+    h.mirai.group_text(GROUP, ADMIN, "关闭 关键词回复");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "已在本群关闭【关键词回复】");
+
+    h.mirai.group_text(GROUP, MEMBER, "你好");
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+
+    // 其他群不受影响
+    h.mirai.group_text(GROUP + 1, MEMBER, "你好");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.target(), Some(GROUP + 1));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_set_role() -> Result<()> {
+    let h = start().await?;
+
+    h.mirai.group_message(
+        GROUP,
+        ADMIN,
+        vec![plain("设置权限 "), at(MEMBER), plain(" 信任")],
+    );
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), format!("已将 {} 设置为【信任】", MEMBER));
+
+    h.mirai.group_text(GROUP, MEMBER, "查看权限");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "当前权限：【信任】");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_command_prefix() -> Result<()> {
+    let h = start_with(|config| config.router.prefixes = vec!["/".to_string()]).await?;
+
+    h.mirai.group_text(GROUP, MEMBER, "ping");
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+
+    // 参数不对时回复用法
+    h.mirai.group_text(GROUP, ADMIN, "/设置权限 信任");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "用法：设置权限 @某人 封禁/普通/信任/群管理");
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+
+    // 关键词回复不是命令，不需要前缀
+    h.mirai.group_text(GROUP, MEMBER, "你好");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "你也好");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fraud_prompt() -> Result<()> {
+    let h = start().await?;
+
+    h.mirai.group_text(GROUP, MEMBER, "诈骗");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "输入诈骗目标视频（av 号、BV 号或者链接）");
+
+    // 没有视频，提示用户后结束，不通知管理员
+    h.mirai.group_text(GROUP, MEMBER, "abc");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "没有找到视频，需要 av 号、BV 号或者视频链接");
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+
+    // av 号和链接都可以，不需要请求 bilibili
+    h.mirai.group_text(GROUP, MEMBER, "诈骗");
+    h.mirai.expect_call("sendGroupMessage").await?;
+    h.mirai.group_text(GROUP, MEMBER, "av170001");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "输入预览中显示的视频");
+    h.mirai.group_text(
+        GROUP,
+        MEMBER,
+        "https://www.bilibili.com/video/BV17b4y1J7ed?p=1",
+    );
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(
+        call.text(),
+        "https://www.bilibili.com/video/av170001?BV17b4y1J7ed"
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_fraud_guard() -> Result<()> {
+    // 获取标题失败时只显示 BV 号，不要等太久
+    let h = start_with(|config| config.http.timeout_seconds = 3).await?;
+
+    // 普通的视频链接不提醒
+    h.mirai.group_text(
+        GROUP,
+        MEMBER,
+        "https://www.bilibili.com/video/BV17x411w7KC?p=1",
+    );
+    h.mirai.expect_silence(Duration::from_secs(1)).await?;
+
+    h.mirai.group_text(
+        GROUP,
+        MEMBER,
+        "快看 www.bilibili.com/video/av170001?BV17b4y1J7ed",
+    );
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert!(call
+        .text()
+        .starts_with("小心！这个链接预览显示的是 BV17b4y1J7ed，实际打开的是 BV17x411w7KC"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown() -> Result<()> {
+    let _ = pretty_env_logger::try_init();
+    let mirai = MockMirai::start().await?;
+    let dir = tempfile::tempdir()?;
+    let config = config(&mirai, &dir)?;
+    let db = sled::open(dir.path())?;
+    let supervisors = Supervisor::accounts(SharedConfig::new(config), db)?;
+    let shutdown = supervisors[0].shutdown().clone();
+
+    let test = async {
+        mirai.wait_connected().await?;
+        mirai.group_text(GROUP, MEMBER, "ping");
+        let call = mirai.expect_call("sendGroupMessage").await?;
+        assert_eq!(call.text(), "pong");
+        // ping 还在等待撤回时退出，撤回仍然会完成
+        shutdown.trigger();
+        mirai.expect_call("recall").await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    let run = async { tokio::join!(Supervisor::run_all(&supervisors), test).1 };
+    tokio::time::timeout(Duration::from_secs(30), run).await??;
+    assert_eq!(shutdown.in_flight(), 0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_weekly_rejected() -> Result<()> {
+    let url = backend(400, r#"{"error": "分类不存在"}"#)?;
+    let h = start_with(|config| {
+        config.asoul_weekly =
+            serde_yaml::from_str(&format!("url: {}\nallow_groups: [{}]", url, GROUP)).unwrap();
+    })
+    .await?;
+
+    // 后端拒绝时把原因回复给用户
+    h.mirai
+        .group_text(GROUP, MEMBER, "修改分类 BV1AR4y147gy 不存在的分类");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    assert_eq!(call.text(), "分类不存在");
+
+    // 审计日志记录为失败
+    h.mirai.group_text(GROUP, ADMIN, "审计 导出");
+    let call = h.mirai.expect_call("sendGroupMessage").await?;
+    let entries: Vec<serde_json::Value> = serde_json::from_str(&call.text())?;
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["success"], false);
+    assert_eq!(entries[0]["result"], "分类不存在");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_multiple_accounts() -> Result<()> {
+    let _ = pretty_env_logger::try_init();
+    let first = MockMirai::start().await?;
+    let second = MockMirai::start().await?;
+    let dir = tempfile::tempdir()?;
+    let config: Config = serde_yaml::from_str(&format!(
+        r"
+accounts:
+  - qq: 1
+    verify_key: key
+    addr: '{}'
+    allow_groups: [{}]
+  - qq: 2
+    verify_key: key
+    addr: '{}'
+    plugins:
+        keyword_reply: false
+db_path: '{}'
+admins: [{}]
+keyword_reply:
+    full_match:
+        你好: 你也好
+asoul_weekly:
+    url: http://localhost
+    allow_groups: []
+",
+        first.addr(),
+        GROUP,
+        second.addr(),
+        dir.path().display(),
+        ADMIN
+    ))?;
+    let db = sled::open(dir.path())?;
+    let supervisors = Supervisor::accounts(SharedConfig::new(config), db)?;
+    assert_eq!(supervisors.len(), 2);
+    for supervisor in supervisors.iter() {
+        let account = supervisor.account();
+        let (bot, con) = miraie::Bot::new(&account.addr, &account.verify_key, account.qq).await?;
+        supervisor.setup(bot);
+        tokio::spawn(con.run());
+    }
+    first.wait_connected().await?;
+    second.wait_connected().await?;
+
+    first.group_text(GROUP, MEMBER, "你好");
+    assert_eq!(
+        first.expect_call("sendGroupMessage").await?.text(),
+        "你也好"
+    );
+    // 第一个账号只在允许的群里响应
+    first.group_text(GROUP + 1, MEMBER, "你好");
+    first.expect_silence(Duration::from_secs(1)).await?;
+
+    // 第二个账号关闭了关键词回复，其他插件照常
+    second.group_text(GROUP + 1, MEMBER, "你好");
+    second.expect_silence(Duration::from_secs(1)).await?;
+    second.group_text(GROUP + 1, MEMBER, "ping");
+    assert_eq!(second.expect_call("sendGroupMessage").await?.text(), "pong");
+    Ok(())
+}